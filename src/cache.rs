@@ -0,0 +1,306 @@
+use crate::config::{Config, Marker, SearchPath, StaticPath, Symlinks};
+use crate::discovery::{Candidate, Collected, Scanner};
+use crate::error::Error;
+use crate::vcs::{GitKind, VcsKind, VcsMarker};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Used when the configuration does not set `cache_ttl`.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// Age after which the cache files of other configurations are removed, long
+/// enough to keep those of contexts switched between, e.g. with `-L`.
+pub const PRUNE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Candidates of a previous scan, stored on disk so the picker can open
+/// without waiting for a new one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cached {
+    /// Seconds since the Unix epoch at which the scan finished.
+    pub created: u64,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Cached {
+    /// Returns whether the scan is older than `ttl`.
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.created) >= ttl.as_secs()
+    }
+}
+
+/// The settings of a configuration that change what a scan finds. Settings
+/// such as the picker or session naming apply after the cache is read and
+/// are left out, so changing them keeps the cache.
+#[derive(Serialize)]
+struct Key<'a> {
+    search_paths: &'a [Option<SearchPath>],
+    entries: &'a Option<Vec<StaticPath>>,
+    nested: Option<bool>,
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
+    exclude: &'a Option<Vec<String>>,
+    default_excludes: Option<bool>,
+    respect_ignore_files: Option<bool>,
+    git_kinds: &'a Option<Vec<GitKind>>,
+    symlinks: Option<Symlinks>,
+    one_file_system: Option<bool>,
+    skip_mounts: &'a Option<Vec<String>>,
+    vcs: &'a Option<Vec<VcsKind>>,
+    vcs_markers: &'a Option<Vec<VcsMarker>>,
+    markers: &'a Option<Vec<Marker>>,
+    socket_name: &'a Option<String>,
+    socket_path: &'a Option<String>,
+}
+
+impl<'a> Key<'a> {
+    fn new(config: &'a Config) -> Self {
+        Key {
+            search_paths: &config.search_paths,
+            entries: &config.entries,
+            nested: config.nested,
+            max_depth: config.max_depth,
+            min_depth: config.min_depth,
+            exclude: &config.exclude,
+            default_excludes: config.default_excludes,
+            respect_ignore_files: config.respect_ignore_files,
+            git_kinds: &config.git_kinds,
+            symlinks: config.symlinks,
+            one_file_system: config.one_file_system,
+            skip_mounts: &config.skip_mounts,
+            vcs: &config.vcs,
+            vcs_markers: &config.vcs_markers,
+            markers: &config.markers,
+            socket_name: &config.socket_name,
+            socket_path: &config.socket_path,
+        }
+    }
+
+    /// Returns the 64-bit FNV-1a hash of the key serialized as JSON, which
+    /// unlike `DefaultHasher` does not change between Rust versions.
+    fn hash(&self) -> u64 {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        json.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
+
+/// The cache file of one configuration, stored as
+/// `$XDG_CACHE_HOME/tmux-sessionizer/<hash of the discovery settings>.json`.
+/// The caches of configurations not stored within [`PRUNE_AGE`] are removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    pub path: PathBuf,
+    /// Age after which the cached candidates need a refresh.
+    pub ttl: Duration,
+}
+
+impl Cache {
+    /// Returns the cache of `config`, or `None` if caching is disabled or
+    /// there is no cache directory.
+    pub fn for_config(config: &Config) -> Option<Cache> {
+        if !config.cache.unwrap_or(true) {
+            return None;
+        }
+        let path = cache_dir()?.join(format!("{:016x}.json", Key::new(config).hash()));
+        Some(Cache {
+            path,
+            ttl: config.cache_ttl.map_or(DEFAULT_TTL, Duration::from_secs),
+        })
+    }
+
+    /// Returns the cached candidates and whether they are older than the
+    /// TTL, or `None` if there are none.
+    pub fn candidates(&self) -> Option<(Vec<Candidate>, bool)> {
+        let cached = self.load()?;
+        let stale = cached.is_stale(self.ttl);
+        Some((cached.candidates, stale))
+    }
+
+    /// Reads the cache, returning `None` if it is missing or unreadable.
+    pub fn load(&self) -> Option<Cached> {
+        let file = fs::File::open(&self.path).ok()?;
+        serde_json::from_reader(BufReader::new(file)).ok()
+    }
+
+    /// Replaces the cache with `candidates` and removes the caches of other
+    /// configurations that have not been used for a while.
+    pub fn store(&self, candidates: Vec<Candidate>) -> Result<(), Error> {
+        let cached = Cached {
+            created: now(),
+            candidates,
+        };
+        self.write(&cached).map_err(|source| Error::Cache {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    /// Stores the candidates of a scan if it completed, a partial list would
+    /// be served as fresh until the cache expires. Returns whether the cache
+    /// still needs a refresh because the scan did not complete.
+    pub fn store_scan(&self, collected: &Collected) -> Result<bool, Error> {
+        if !collected.completed {
+            return Ok(true);
+        }
+        self.store(collected.candidates.clone())?;
+        Ok(false)
+    }
+
+    /// Scans with `scanner` and stores the result if the scan completed.
+    /// Returns the warnings of the scan.
+    pub fn update(&self, scanner: &Scanner) -> Result<Vec<Error>, Error> {
+        let collected = scanner.collect();
+        self.store_scan(&collected)?;
+        Ok(collected.warnings)
+    }
+
+    fn write(&self, cached: &Cached) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first so readers never see a partial cache
+        let tmp = self
+            .path
+            .with_extension(format!("json.{}", std::process::id()));
+        let file = fs::File::create(&tmp)?;
+        serde_json::to_writer(BufWriter::new(file), cached)?;
+        fs::rename(&tmp, &self.path)?;
+        self.prune();
+        Ok(())
+    }
+
+    /// Removes the cache files of other configurations that were not written
+    /// within [`PRUNE_AGE`], which are left behind whenever the discovery
+    /// settings change.
+    fn prune(&self) {
+        let dir = match self.path.parent().map(fs::read_dir) {
+            Some(Ok(dir)) => dir,
+            _ => return,
+        };
+        for entry in dir.filter_map(Result::ok) {
+            let path = entry.path();
+            let unused = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= PRUNE_AGE);
+            if path != self.path
+                && unused
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension == "json")
+            {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Candidates returned by [`candidates`].
+#[derive(Debug, Default)]
+pub struct Served {
+    pub candidates: Vec<Candidate>,
+    pub warnings: Vec<Error>,
+    /// Whether the cache should be refreshed, e.g. in the background with
+    /// [`Cache::update`], because it is stale or the scan did not complete.
+    pub needs_refresh: bool,
+}
+
+/// Returns the candidates of `scanner`: the cached ones unless `refresh` is
+/// set, and otherwise those of a new scan, which is stored if it completed.
+pub fn candidates(cache: Option<&Cache>, scanner: &Scanner, refresh: bool) -> Served {
+    if let Some((candidates, stale)) = cache.filter(|_| !refresh).and_then(Cache::candidates) {
+        return Served {
+            candidates,
+            warnings: Vec::new(),
+            needs_refresh: stale,
+        };
+    }
+
+    let mut collected = scanner.collect();
+    let needs_refresh = match cache.map(|cache| cache.store_scan(&collected)) {
+        Some(Ok(needs_refresh)) => needs_refresh,
+        Some(Err(err)) => {
+            collected.warnings.push(err);
+            false
+        }
+        None => false,
+    };
+    Served {
+        candidates: collected.candidates,
+        warnings: collected.warnings,
+        needs_refresh,
+    }
+}
+
+/// Returns `$XDG_CACHE_HOME/tmux-sessionizer`, falling back to
+/// `~/.cache/tmux-sessionizer`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME").filter(|dir| !dir.is_empty())?).join(".cache"),
+    };
+    Some(base.join("tmux-sessionizer"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Picker;
+
+    #[test]
+    fn key_ignores_settings_applied_after_discovery() {
+        let config = Config::default();
+        let mut changed = config.clone();
+        changed.picker = Some(Picker::Builtin);
+        changed.cache_ttl = Some(1);
+        changed.session_name_replacement = Some('-');
+        changed.lowercase_session_names = Some(true);
+        assert_eq!(Key::new(&config).hash(), Key::new(&changed).hash());
+    }
+
+    #[test]
+    fn key_depends_on_discovery_settings() {
+        let config = Config::default();
+        let mut changed = config.clone();
+        changed.max_depth = Some(3);
+        assert_ne!(Key::new(&config).hash(), Key::new(&changed).hash());
+    }
+
+    #[test]
+    fn prune_keeps_recently_used_caches() {
+        let dir = env::temp_dir().join(format!("tmux-sessionizer-prune-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("old.json");
+        let recent = dir.join("recent.json");
+        fs::write(&old, "{}").unwrap();
+        fs::write(&recent, "{}").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - PRUNE_AGE)
+            .unwrap();
+
+        let cache = Cache {
+            path: dir.join("current.json"),
+            ttl: DEFAULT_TTL,
+        };
+        cache.store(Vec::new()).unwrap();
+        let left = (old.exists(), recent.exists(), cache.path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, (false, true, true));
+    }
+}
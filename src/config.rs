@@ -0,0 +1,310 @@
+use crate::error::Error;
+use crate::session;
+use crate::vcs::{GitKind, VcsKind, VcsMarker};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Contents of the YAML configuration file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Directories that are searched for projects, `~` is expanded.
+    pub search_paths: Vec<Option<SearchPath>>,
+    /// Directories that are always listed, whether they are projects or not.
+    pub entries: Option<Vec<StaticPath>>,
+    /// Whether repositories nested inside other repositories are reported as well.
+    pub nested: Option<bool>,
+    /// How many directory levels below a search path are searched, unlimited by default.
+    pub max_depth: Option<usize>,
+    /// How many directory levels below a search path a project has to be at
+    /// least. `0` lets the search path itself be a project, defaults to `1`.
+    pub min_depth: Option<usize>,
+    /// Glob patterns of directories that are not searched, see [`Exclude`](crate::exclude::Exclude).
+    pub exclude: Option<Vec<String>>,
+    /// Whether the built-in [`DEFAULT_EXCLUDES`](crate::exclude::DEFAULT_EXCLUDES)
+    /// are pruned as well, defaults to `true`.
+    pub default_excludes: Option<bool>,
+    /// Whether directories ignored by `.gitignore`, `.ignore` or
+    /// `.sessionizerignore` files are skipped, defaults to `false`.
+    pub respect_ignore_files: Option<bool>,
+    /// Which kinds of git checkouts are listed, all of them by default.
+    pub git_kinds: Option<Vec<GitKind>>,
+    /// How symbolic links to directories are treated, defaults to
+    /// [`Symlinks::Follow`].
+    pub symlinks: Option<Symlinks>,
+    /// Whether the search stays on the file system of the search path,
+    /// defaults to `false`.
+    pub one_file_system: Option<bool>,
+    /// Mount points that are never searched, `~` is expanded.
+    pub skip_mounts: Option<Vec<String>>,
+    /// Seconds after which a scan gives up and the projects found until
+    /// then are listed, unlimited by default.
+    pub scan_timeout: Option<u64>,
+    /// Which built-in version control systems are detected, all of them by default.
+    pub vcs: Option<Vec<VcsKind>>,
+    /// Additional version control systems, recognized by a marker file or directory.
+    pub vcs_markers: Option<Vec<VcsMarker>>,
+    /// Files or directories that make the directory containing them a
+    /// project, such as `Cargo.toml` or `flake.nix`.
+    pub markers: Option<Vec<Marker>>,
+    /// Whether discovered projects are cached on disk, defaults to `true`.
+    pub cache: Option<bool>,
+    /// Seconds after which the cache is refreshed in the background,
+    /// defaults to [`DEFAULT_TTL`](crate::cache::DEFAULT_TTL).
+    pub cache_ttl: Option<u64>,
+    /// Which picker projects are chosen with, defaults to [`Picker::Fzf`].
+    pub picker: Option<Picker>,
+    /// Character that replaces characters tmux cannot handle in session names, defaults to `_`.
+    pub session_name_replacement: Option<char>,
+    /// Whether session names are converted to lowercase.
+    pub lowercase_session_names: Option<bool>,
+    /// Name of the tmux server socket, like `tmux -L`.
+    pub socket_name: Option<String>,
+    /// Path of the tmux server socket, like `tmux -S`. Takes precedence over `socket_name`.
+    pub socket_path: Option<String>,
+}
+
+/// The picker projects are chosen with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Picker {
+    /// The external `fzf` binary.
+    Fzf,
+    /// The fuzzy finder built into this program, for systems without `fzf`.
+    Builtin,
+}
+
+/// How symbolic links to directories are treated while searching. Whichever
+/// way a project is reached, it is listed once under its canonical path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Symlinks {
+    /// Links are followed, links back to a directory that is currently
+    /// being searched are not.
+    Follow,
+    /// Links are neither followed nor reported.
+    Skip,
+    /// Links are followed, but not the links found below a followed one.
+    FollowOnce,
+}
+
+/// An entry of `search_paths`, either a plain path or a map that overrides
+/// the global settings for this path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SearchPath {
+    Path(String),
+    Entry(SearchPathEntry),
+}
+
+/// A search path with its own settings. Unset settings fall back to the
+/// global ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchPathEntry {
+    pub path: String,
+    pub nested: Option<bool>,
+    pub max_depth: Option<usize>,
+    pub min_depth: Option<usize>,
+    /// Added to the global `exclude` patterns.
+    pub exclude: Option<Vec<String>>,
+    pub default_excludes: Option<bool>,
+    pub respect_ignore_files: Option<bool>,
+    pub git_kinds: Option<Vec<GitKind>>,
+    pub symlinks: Option<Symlinks>,
+    pub one_file_system: Option<bool>,
+    /// Prepended to the session names of all projects found below this path.
+    pub session_prefix: Option<String>,
+    pub socket_name: Option<String>,
+    pub socket_path: Option<String>,
+}
+
+impl SearchPath {
+    /// Returns this search path as an entry, turning a plain path into an
+    /// entry without overrides.
+    pub fn to_entry(&self) -> SearchPathEntry {
+        match self {
+            SearchPath::Path(path) => SearchPathEntry {
+                path: path.clone(),
+                ..SearchPathEntry::default()
+            },
+            SearchPath::Entry(entry) => entry.clone(),
+        }
+    }
+}
+
+/// An entry of `entries`, either a plain path or a map with settings for
+/// this path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StaticPath {
+    Path(String),
+    Entry(StaticPathEntry),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticPathEntry {
+    pub path: String,
+    /// Shown in the picker instead of the path.
+    pub name: Option<String>,
+    /// Used instead of the session name derived from the path.
+    pub session_name: Option<String>,
+    pub socket_name: Option<String>,
+    pub socket_path: Option<String>,
+}
+
+impl StaticPath {
+    /// Returns this path as an entry, turning a plain path into an entry
+    /// without settings.
+    pub fn to_entry(&self) -> StaticPathEntry {
+        match self {
+            StaticPath::Path(path) => StaticPathEntry {
+                path: path.clone(),
+                ..StaticPathEntry::default()
+            },
+            StaticPath::Entry(entry) => entry.clone(),
+        }
+    }
+}
+
+/// An entry of `markers`, either a plain file name or a map with settings
+/// for this marker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Marker {
+    Name(String),
+    Entry(MarkerEntry),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarkerEntry {
+    pub name: String,
+    /// Whether matches inside an already found project are reported,
+    /// defaults to the search path's `nested` setting.
+    pub nested: Option<bool>,
+}
+
+impl Marker {
+    /// Returns this marker as an entry, turning a plain name into an entry
+    /// without settings.
+    pub fn to_entry(&self) -> MarkerEntry {
+        match self {
+            Marker::Name(name) => MarkerEntry {
+                name: name.clone(),
+                ..MarkerEntry::default()
+            },
+            Marker::Entry(entry) => entry.clone(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            search_paths: vec![
+                Some(SearchPath::Path("~/".to_string())),
+                Some(SearchPath::Path("~/projects".to_string())),
+            ],
+            entries: None,
+            nested: None,
+            max_depth: None,
+            min_depth: None,
+            exclude: None,
+            default_excludes: None,
+            respect_ignore_files: None,
+            git_kinds: None,
+            symlinks: None,
+            one_file_system: None,
+            skip_mounts: None,
+            scan_timeout: None,
+            vcs: None,
+            vcs_markers: None,
+            markers: None,
+            cache: None,
+            cache_ttl: None,
+            picker: None,
+            session_name_replacement: None,
+            lowercase_session_names: None,
+            socket_name: None,
+            socket_path: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or from the file found by
+    /// [`find_config_file`] if `path` is `None`. Falls back to
+    /// [`Config::default`] when there is no file. Returns the path of the
+    /// file that was loaded along with the configuration.
+    pub fn load(path: Option<PathBuf>) -> Result<(Config, Option<PathBuf>), Error> {
+        match path.or_else(find_config_file) {
+            Some(path) => Ok((Config::from_file(path.clone())?, Some(path))),
+            None => Ok((Config::default(), None)),
+        }
+    }
+
+    /// Reads, parses and validates the configuration file at `path`.
+    pub fn from_file(path: PathBuf) -> Result<Config, Error> {
+        let config_content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(source) => return Err(Error::ConfigRead { path, source }),
+        };
+        let config: Config =
+            serde_yaml::from_str(&config_content).map_err(|err| Error::config_parse(path, err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Uses the tmux server behind `socket_name` or `socket_path` for every
+    /// project, replacing the global socket as well as the sockets of
+    /// individual search paths and entries, like `-L` and `-S` do.
+    pub fn override_socket(&mut self, socket_name: Option<String>, socket_path: Option<String>) {
+        self.socket_name = socket_name;
+        self.socket_path = socket_path;
+        for search_path in self.search_paths.iter_mut().flatten() {
+            if let SearchPath::Entry(entry) = search_path {
+                entry.socket_name = None;
+                entry.socket_path = None;
+            }
+        }
+        for entry in self.entries.iter_mut().flatten() {
+            if let StaticPath::Entry(entry) = entry {
+                entry.socket_name = None;
+                entry.socket_path = None;
+            }
+        }
+    }
+
+    /// Checks settings that parse but cannot be used, such as a
+    /// `session_name_replacement` that tmux cannot handle either.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self.session_name_replacement {
+            Some(c) if !session::is_safe(c) => Err(Error::InvalidReplacement(c)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns `$TMUX_SESSIONIZER_CONFIG` if it is set, whether the file exists
+/// or not, and otherwise the first existing file out of
+/// `$XDG_CONFIG_HOME/tmux-sessionizer/config.yaml` and
+/// `~/.config/tmux-sessionizer/config.yaml`.
+pub fn find_config_file() -> Option<PathBuf> {
+    // an explicitly chosen file has to exist, like one passed with --config
+    if let Some(path) = env::var_os("TMUX_SESSIONIZER_CONFIG").filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        candidates.push(PathBuf::from(dir).join("tmux-sessionizer/config.yaml"));
+    }
+    candidates.push(PathBuf::from(
+        shellexpand::tilde("~/.config/tmux-sessionizer/config.yaml").to_string(),
+    ));
+
+    candidates.into_iter().find(|path| path.is_file())
+}
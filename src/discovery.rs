@@ -0,0 +1,1202 @@
+use crate::config::{Config, StaticPath, StaticPathEntry, Symlinks};
+use crate::error::Error;
+use crate::exclude::{Exclude, DEFAULT_EXCLUDES};
+use crate::session::{Sanitizer, Socket};
+use crate::vcs::{self, Detector, GitKind, Vcs};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use path_clean::PathClean;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A directory that can be opened as a tmux session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Project {
+    pub path: PathBuf,
+    /// Name shown in the picker instead of the path.
+    pub name: Option<String>,
+    pub session_name: String,
+    /// Version control system of the checkout, `None` for projects found by a marker.
+    pub vcs: Option<Vcs>,
+    /// Marker file the project was found by, `None` for checkouts.
+    pub marker: Option<String>,
+    /// tmux server the session lives on, if it differs from the default one.
+    pub socket: Option<Socket>,
+}
+
+impl Project {
+    /// Creates a project named after the last component of `path`.
+    pub fn new(path: PathBuf, vcs: Option<Vcs>, sanitizer: &Sanitizer) -> Result<Project, Error> {
+        let session_name = session_name(&path, 1, None, sanitizer)
+            .ok_or_else(|| Error::NonUtf8Path(path.clone()))?;
+        Ok(Project {
+            path,
+            name: None,
+            session_name,
+            vcs,
+            marker: None,
+            socket: None,
+        })
+    }
+
+    /// Returns the text the project is listed as in the picker, its name if
+    /// it has one and its path otherwise.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// A project found by a scan, before it is named. Session names can only be
+/// made unique once all candidates are known, see [`name_projects`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub vcs: Option<Vcs>,
+    pub marker: Option<String>,
+    /// Name shown in the picker instead of the path.
+    pub name: Option<String>,
+    /// Session name that is used as is instead of being derived from the path.
+    pub session_name: Option<String>,
+    pub session_prefix: Option<String>,
+    pub socket: Option<Socket>,
+}
+
+impl Candidate {
+    /// Returns the text the candidate is listed as in the picker, the same
+    /// as [`Project::label`] of the project it turns into.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// Sent by [`Scanner::run`] while it walks the search roots.
+#[derive(Debug)]
+pub enum ScanEvent {
+    Found(Candidate),
+    /// A problem that did not stop the scan, such as an unreadable directory.
+    Warning(Error),
+    /// A search root was searched completely.
+    Finished(PathBuf),
+}
+
+/// A marker file with its `nested` setting resolved against the search root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectMarker {
+    pub name: String,
+    /// Whether matches inside an already found project are reported.
+    pub nested: bool,
+}
+
+/// A search path with its settings resolved against the global configuration.
+#[derive(Debug, Clone)]
+pub struct SearchRoot {
+    pub path: PathBuf,
+    pub nested: bool,
+    pub max_depth: Option<usize>,
+    pub min_depth: usize,
+    pub exclude: Exclude,
+    pub respect_ignore_files: bool,
+    pub git_kinds: Vec<GitKind>,
+    pub symlinks: Symlinks,
+    pub one_file_system: bool,
+    /// Mount points that are not descended into.
+    pub skip_mounts: Vec<PathBuf>,
+    pub detector: Detector,
+    pub markers: Vec<ProjectMarker>,
+    pub session_prefix: Option<String>,
+    pub socket: Option<Socket>,
+}
+
+/// Result of a discovery run. Problems that did not stop the scan, such as
+/// unreadable directories, are collected in `warnings`.
+#[derive(Debug, Default)]
+pub struct Discovery {
+    pub projects: Vec<Project>,
+    pub warnings: Vec<Error>,
+}
+
+/// Searches all `search_paths` of `config` for projects.
+pub fn discover(config: &Config) -> Result<Discovery, Error> {
+    let Collected {
+        candidates,
+        mut warnings,
+        ..
+    } = Scanner::new(config)?.collect();
+    let projects = name_projects(candidates, &Sanitizer::from_config(config), &mut warnings);
+    Ok(Discovery { projects, warnings })
+}
+
+/// Everything a scan reported, see [`Scan::collect_with`].
+#[derive(Debug, Default)]
+pub struct Collected {
+    pub candidates: Vec<Candidate>,
+    pub warnings: Vec<Error>,
+    /// Whether the scan ran to completion, rather than being cancelled or
+    /// timing out.
+    pub completed: bool,
+    /// Index of each path in `candidates` while collecting.
+    positions: HashMap<PathBuf, usize>,
+}
+
+impl Collected {
+    /// Adds a candidate reported by a scan, replacing the one reported earlier
+    /// with the same path by a search root that did not own it.
+    fn push(&mut self, candidate: Candidate) {
+        match self.positions.get(&candidate.path) {
+            Some(&i) => self.candidates[i] = candidate,
+            None => {
+                self.positions
+                    .insert(candidate.path.clone(), self.candidates.len());
+                self.candidates.push(candidate);
+            }
+        }
+    }
+}
+
+/// Walks the search roots and static entries of a configuration, reporting
+/// each candidate as soon as it is found.
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    roots: Vec<SearchRoot>,
+    entries: Vec<StaticPathEntry>,
+    detector: Detector,
+    socket: Option<Socket>,
+    timeout: Option<Duration>,
+}
+
+impl Scanner {
+    /// Resolves the search roots of `config`, failing if an exclude pattern
+    /// is invalid.
+    pub fn new(config: &Config) -> Result<Scanner, Error> {
+        Ok(Scanner {
+            roots: search_roots(config)?,
+            entries: config
+                .entries
+                .iter()
+                .flatten()
+                .map(StaticPath::to_entry)
+                .collect(),
+            detector: Detector::from_config(config),
+            socket: Socket::from_config(config),
+            timeout: config.scan_timeout.map(Duration::from_secs),
+        })
+    }
+
+    /// Runs the scan on a background thread. The returned [`Scan`] yields
+    /// its events until it finishes or the configured `scan_timeout` passes.
+    pub fn spawn(&self, cancel: Arc<AtomicBool>) -> Scan {
+        let (sender, events) = mpsc::channel();
+        let scanner = self.clone();
+        let handle = {
+            let cancel = Arc::clone(&cancel);
+            thread::spawn(move || scanner.run(&cancel, sender))
+        };
+        Scan {
+            events,
+            cancel,
+            timeout: self.timeout,
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+            unfinished: self.roots.iter().map(|root| root.path.clone()).collect(),
+            timed_out: false,
+            handle: Some(handle),
+        }
+    }
+
+    /// Runs the scan to completion or until it times out, and returns the
+    /// candidates in the order of [`sort`](Self::sort).
+    pub fn collect(&self) -> Collected {
+        let mut collected = self
+            .spawn(Arc::new(AtomicBool::new(false)))
+            .collect_with(|_| {});
+        self.sort(&mut collected.candidates);
+        collected
+    }
+
+    /// Sends the static entries and then everything found below the search
+    /// roots to `sender`, each path once or, when a root that owns it finds
+    /// it later, again to replace the earlier report, followed by
+    /// [`ScanEvent::Finished`] for each root. The roots are walked in
+    /// parallel. Returns early once `cancel` is set or the receiver is gone,
+    /// and whether the scan ran to completion.
+    pub fn run(&self, cancel: &AtomicBool, sender: Sender<ScanEvent>) -> bool {
+        let send = |event: ScanEvent| {
+            if sender.send(event).is_err() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        };
+        let root_paths: Vec<PathBuf> = self.roots.iter().map(|root| root.path.clone()).collect();
+        let claims = Claims::new(&root_paths);
+
+        self.static_entries(&|event| claims.report(None, event, &send));
+
+        self.roots.par_iter().enumerate().for_each(|(i, root)| {
+            let emit = |event| claims.report(Some(i), event, &send);
+            let metadata = match fs::metadata(&root.path) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    emit(ScanEvent::Warning(Error::MissingSearchPath(
+                        root.path.clone(),
+                    )));
+                    emit(ScanEvent::Finished(root.path.clone()));
+                    return;
+                }
+            };
+            let visit = Visit {
+                id: (metadata.dev(), metadata.ino()),
+                through_link: false,
+                parent: None,
+            };
+            Walker {
+                root,
+                skip: &root_paths,
+                cancel,
+                emit: &emit,
+            }
+            .walk(&root.path, 0, false, None, &visit);
+            if !cancel.load(Ordering::Relaxed) {
+                emit(ScanEvent::Finished(root.path.clone()));
+            }
+        });
+
+        !cancel.load(Ordering::Relaxed)
+    }
+
+    /// Puts candidates collected from [`run`](Self::run), which arrive in no
+    /// particular order, into a stable one: static entries as configured,
+    /// then the projects of each search root sorted by path, the roots as
+    /// configured.
+    pub fn sort(&self, found: &mut [Candidate]) {
+        // static entries are reported as configured, found projects canonically
+        let canonical = |path: PathBuf| path.canonicalize().unwrap_or(path);
+        let entries: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .map(|entry| expand(&entry.path))
+            .collect();
+        let roots: Vec<PathBuf> = self
+            .roots
+            .iter()
+            .map(|root| canonical(root.path.clone()))
+            .collect();
+        let rank =
+            |candidate: &Candidate| match entries.iter().position(|entry| *entry == candidate.path)
+            {
+                Some(i) => (0, i),
+                None => (
+                    1,
+                    owning_root(&roots, &candidate.path).unwrap_or(roots.len()),
+                ),
+            };
+        found.sort_by_cached_key(|candidate| (rank(candidate), candidate.path.clone()));
+    }
+
+    /// Reports the static entries, skipping those that do not exist.
+    fn static_entries(&self, emit: &(dyn Fn(ScanEvent) + Sync)) {
+        for entry in &self.entries {
+            let path = expand(&entry.path);
+            if !path.is_dir() {
+                emit(ScanEvent::Warning(Error::MissingEntry(path)));
+                continue;
+            }
+
+            let socket = Socket::new(entry.socket_name.as_deref(), entry.socket_path.as_deref())
+                .or_else(|| self.socket.clone());
+            emit(ScanEvent::Found(Candidate {
+                vcs: self.detector.detect(&path),
+                marker: None,
+                name: entry.name.clone(),
+                session_name: entry.session_name.clone(),
+                session_prefix: None,
+                socket,
+                path,
+            }));
+        }
+    }
+}
+
+/// Returns the index of the innermost of the canonical `roots` containing
+/// `path`, as search roots may be nested.
+fn owning_root(roots: &[PathBuf], path: &Path) -> Option<usize> {
+    roots
+        .iter()
+        .enumerate()
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())
+        .map(|(i, _)| i)
+}
+
+/// Decides which search root reports a project found by more than one of
+/// them, for example through symlinks, so that its per-path settings do not
+/// depend on which walk got there first. Static entries always win, then the
+/// root owning the project as in [`Scanner::sort`], then the roots as
+/// configured. A project is reported as soon as it is found, and again if a
+/// root that wins it finds it later, replacing the earlier report in
+/// [`Collected`]. A project is never lost to a root that does not finish.
+struct Claims {
+    roots: Vec<PathBuf>,
+    /// Rank of the root each path was reported by, `None` for static entries.
+    reported: Mutex<HashMap<PathBuf, Option<(bool, usize)>>>,
+}
+
+impl Claims {
+    fn new(roots: &[PathBuf]) -> Claims {
+        Claims {
+            roots: roots
+                .iter()
+                .map(|root| root.canonicalize().unwrap_or_else(|_| root.clone()))
+                .collect(),
+            reported: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Passes `event` from the root at index `root`, or from the static
+    /// entries if `None`, on to `send`, unless it is a project already
+    /// reported by a root that wins it.
+    fn report(&self, root: Option<usize>, event: ScanEvent, send: &dyn Fn(ScanEvent)) {
+        let mut candidate = match event {
+            ScanEvent::Found(candidate) => candidate,
+            event => return send(event),
+        };
+        let path = candidate
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| candidate.path.clone());
+        // static entries are listed as configured, found projects canonically
+        if root.is_some() {
+            candidate.path = path.clone();
+        }
+        let rank = root.map(|root| self.rank(root, &path));
+        // keep the lock while sending, so a better report always comes last
+        let mut reported = self.reported.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(best) = reported.get(&path) {
+            if *best <= rank {
+                return;
+            }
+        }
+        reported.insert(path, rank);
+        send(ScanEvent::Found(candidate));
+    }
+
+    /// Orders the roots finding `path`, the smallest one wins.
+    fn rank(&self, root: usize, path: &Path) -> (bool, usize) {
+        (owning_root(&self.roots, path) != Some(root), root)
+    }
+}
+
+/// A scan running on a background thread, see [`Scanner::spawn`]. Once the
+/// timeout passes the scan is cancelled and every root that did not finish is
+/// reported as [`Error::ScanTimeout`]. Roots stuck in a hanging file system
+/// call are abandoned rather than waited for.
+#[derive(Debug)]
+pub struct Scan {
+    events: Receiver<ScanEvent>,
+    cancel: Arc<AtomicBool>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    /// Roots without a [`ScanEvent::Finished`] yet.
+    unfinished: Vec<PathBuf>,
+    timed_out: bool,
+    handle: Option<JoinHandle<bool>>,
+}
+
+impl Scan {
+    /// Receives all events, calling `on_event` for each as it arrives, but
+    /// not for a candidate replacing an earlier report of the same path,
+    /// which is listed the same. The candidates are returned in the order
+    /// they were found.
+    pub fn collect_with(mut self, mut on_event: impl FnMut(&ScanEvent)) -> Collected {
+        let mut collected = Collected::default();
+        for event in &mut self {
+            let replaces = matches!(
+                &event,
+                ScanEvent::Found(candidate) if collected.positions.contains_key(&candidate.path)
+            );
+            if !replaces {
+                on_event(&event);
+            }
+            match event {
+                ScanEvent::Found(candidate) => collected.push(candidate),
+                ScanEvent::Warning(warning) => collected.warnings.push(warning),
+                ScanEvent::Finished(_) => {}
+            }
+        }
+        collected.completed = self.completed();
+        collected
+    }
+
+    /// Returns whether the scan ran to completion, without waiting for a
+    /// scan that timed out.
+    pub fn completed(mut self) -> bool {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return false,
+        };
+        if self.timed_out && !handle.is_finished() {
+            return false;
+        }
+        handle.join().unwrap_or(false)
+    }
+}
+
+impl Iterator for Scan {
+    type Item = ScanEvent;
+
+    fn next(&mut self) -> Option<ScanEvent> {
+        if self.timed_out {
+            if self.unfinished.is_empty() {
+                return None;
+            }
+            return Some(ScanEvent::Warning(Error::ScanTimeout {
+                path: self.unfinished.remove(0),
+                timeout: self.timeout.unwrap_or_default(),
+            }));
+        }
+
+        let event = match self.deadline {
+            Some(deadline) => {
+                match self
+                    .events
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.cancel.store(true, Ordering::Relaxed);
+                        self.timed_out = true;
+                        return self.next();
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            }
+            None => self.events.recv().ok()?,
+        };
+        if let ScanEvent::Finished(root) = &event {
+            self.unfinished.retain(|path| path != root);
+        }
+        Some(event)
+    }
+}
+
+/// Resolves the `search_paths` of `config`: expands `~`, normalizes the
+/// paths and drops duplicates, keeping the first occurrence. Fails if an
+/// exclude pattern is invalid.
+pub fn search_roots(config: &Config) -> Result<Vec<SearchRoot>, Error> {
+    let mut roots: Vec<SearchRoot> = Vec::new();
+    let detector = Detector::from_config(config);
+    let skip_mounts: Vec<PathBuf> = config
+        .skip_mounts
+        .iter()
+        .flatten()
+        .map(|path| expand(path))
+        .collect();
+
+    for entry in config.search_paths.iter().flatten() {
+        let entry = entry.to_entry();
+        let path = expand(&entry.path);
+        if roots.iter().any(|root| root.path == path) {
+            continue;
+        }
+
+        let mut patterns: Vec<&str> = Vec::new();
+        if entry
+            .default_excludes
+            .or(config.default_excludes)
+            .unwrap_or(true)
+        {
+            patterns.extend(DEFAULT_EXCLUDES);
+        }
+        patterns.extend(config.exclude.iter().flatten().map(String::as_str));
+        patterns.extend(entry.exclude.iter().flatten().map(String::as_str));
+        let exclude = Exclude::new(&path, patterns)?;
+
+        let nested = entry.nested.or(config.nested).unwrap_or(false);
+        let markers = config
+            .markers
+            .iter()
+            .flatten()
+            .map(|marker| {
+                let marker = marker.to_entry();
+                ProjectMarker {
+                    name: marker.name,
+                    nested: marker.nested.unwrap_or(nested),
+                }
+            })
+            .collect();
+
+        let socket = Socket::new(entry.socket_name.as_deref(), entry.socket_path.as_deref())
+            .or_else(|| Socket::from_config(config));
+        roots.push(SearchRoot {
+            path,
+            nested,
+            max_depth: entry.max_depth.or(config.max_depth),
+            min_depth: entry.min_depth.or(config.min_depth).unwrap_or(1),
+            exclude,
+            respect_ignore_files: entry
+                .respect_ignore_files
+                .or(config.respect_ignore_files)
+                .unwrap_or(false),
+            git_kinds: entry
+                .git_kinds
+                .or_else(|| config.git_kinds.clone())
+                .unwrap_or_else(|| GitKind::ALL.to_vec()),
+            symlinks: entry
+                .symlinks
+                .or(config.symlinks)
+                .unwrap_or(Symlinks::Follow),
+            one_file_system: entry
+                .one_file_system
+                .or(config.one_file_system)
+                .unwrap_or(false),
+            skip_mounts: skip_mounts.clone(),
+            detector: detector.clone(),
+            markers,
+            session_prefix: entry.session_prefix,
+            socket,
+        });
+    }
+
+    Ok(roots)
+}
+
+/// Expands `~` in a configured path and normalizes it.
+fn expand(path: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(path).to_string()).clean()
+}
+
+/// Ignore files that are honored when `respect_ignore_files` is enabled.
+pub const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore", ".sessionizerignore"];
+
+/// Recursively reports every checkout the root's detector recognizes and
+/// every directory containing one of the root's markers, as long as it lies
+/// between the root's `min_depth` and `max_depth`.
+///
+/// Excluded directories, and ignored ones if the root respects ignore files,
+/// are pruned without being read, as are bare git repositories, whose linked
+/// worktrees are reported instead.
+///
+/// Directories listed in `skip`, usually the other search roots, are left to
+/// their own scan, as are the configured mount points and, if the root stays
+/// on one file system, other devices. Symlinks are followed as the root's
+/// `symlinks` setting says, but never back to a directory being walked.
+///
+/// Subdirectories are walked in parallel, so candidates are reported in no
+/// particular order.
+struct Walker<'a> {
+    root: &'a SearchRoot,
+    skip: &'a [PathBuf],
+    cancel: &'a AtomicBool,
+    emit: &'a (dyn Fn(ScanEvent) + Sync),
+}
+
+/// The ignore files of one directory, linked to those of the closest parent
+/// directory that has any.
+struct Ignores<'a> {
+    ignore: Gitignore,
+    parent: Option<&'a Ignores<'a>>,
+}
+
+impl Ignores<'_> {
+    /// Returns whether the directory at `path` is ignored, the innermost
+    /// ignore file with a matching rule decides.
+    fn is_ignored(&self, path: &Path) -> bool {
+        for ignores in std::iter::successors(Some(self), |ignores| ignores.parent) {
+            match ignores.ignore.matched(path, true) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// A directory on the path from the root to the one being walked, used to
+/// avoid following symlinks in circles.
+struct Visit<'a> {
+    /// Device and inode of the directory.
+    id: (u64, u64),
+    /// Whether the directory was reached through a symlink.
+    through_link: bool,
+    parent: Option<&'a Visit<'a>>,
+}
+
+impl Visit<'_> {
+    fn ancestors(&self) -> impl Iterator<Item = &Visit<'_>> {
+        std::iter::successors(Some(self), |visit| visit.parent)
+    }
+
+    /// Returns the device the search root is on.
+    fn root_device(&self) -> u64 {
+        self.ancestors().last().map_or(self.id.0, |root| root.id.0)
+    }
+}
+
+impl Walker<'_> {
+    /// Walks `dir`, `ignores` holds the ignore files of the directories
+    /// between the root and `dir` and `visit` the directories themselves.
+    fn walk(
+        &self,
+        dir: &Path,
+        depth: usize,
+        mut inside_project: bool,
+        ignores: Option<&Ignores>,
+        visit: &Visit,
+    ) {
+        if self.cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let below_min_depth = depth < self.root.min_depth;
+
+        if let Some(vcs) = self.root.detector.detect(dir) {
+            let is_bare = vcs == Vcs::Git(GitKind::Bare);
+            if !below_min_depth {
+                if !inside_project || self.root.nested {
+                    self.push(dir.to_path_buf(), Some(vcs), None);
+                }
+                inside_project = true;
+            }
+            if is_bare {
+                for worktree in vcs::bare_worktrees(dir) {
+                    let vcs = Vcs::Git(GitKind::Worktree);
+                    self.push(worktree, Some(vcs), None);
+                }
+                return;
+            }
+        } else if let Some(marker) = self
+            .root
+            .markers
+            .iter()
+            .find(|marker| dir.join(&marker.name).exists())
+        {
+            if !below_min_depth {
+                if !inside_project || marker.nested {
+                    let name = Some(marker.name.clone());
+                    self.push(dir.to_path_buf(), None, name);
+                }
+                inside_project = true;
+            }
+        }
+        if inside_project
+            && !self.root.nested
+            && !self.root.markers.iter().any(|marker| marker.nested)
+        {
+            return;
+        }
+        if self
+            .root
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth)
+        {
+            return;
+        }
+
+        let entries: Vec<fs::DirEntry> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(Result::ok).collect(),
+            Err(source) => {
+                (self.emit)(ScanEvent::Warning(Error::UnreadableDirectory {
+                    path: dir.to_path_buf(),
+                    source,
+                }));
+                return;
+            }
+        };
+
+        let own;
+        let ignores = match self.read_ignore(dir) {
+            Some(ignore) => {
+                own = Ignores {
+                    ignore,
+                    parent: ignores,
+                };
+                Some(&own)
+            }
+            None => ignores,
+        };
+        entries.into_par_iter().for_each(|entry| {
+            let path = entry.path();
+            if path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| self.root.detector.is_metadata(name))
+                || self.skip.contains(&path)
+                // checked before the directory is touched, a stale mount may hang
+                || self.root.skip_mounts.contains(&path)
+                || self.root.exclude.is_excluded(&path)
+                || ignores.is_some_and(|ignores| ignores.is_ignored(&path))
+            {
+                return;
+            }
+            let child = match self.visit(&entry, visit) {
+                Some(child) => child,
+                None => return,
+            };
+
+            self.walk(&path, depth + 1, inside_project, ignores, &child);
+        });
+    }
+
+    /// Returns how `entry` of the directory `parent` is visited, or `None`
+    /// if it is no directory or a symlink that is not followed.
+    fn visit<'v>(&self, entry: &fs::DirEntry, parent: &'v Visit<'v>) -> Option<Visit<'v>> {
+        let is_link = entry.file_type().ok()?.is_symlink();
+        let metadata = if is_link {
+            let follow = match self.root.symlinks {
+                Symlinks::Follow => true,
+                Symlinks::Skip => false,
+                Symlinks::FollowOnce => !parent.through_link,
+            };
+            if !follow {
+                return None;
+            }
+            fs::metadata(entry.path()).ok()?
+        } else {
+            entry.metadata().ok()?
+        };
+        if !metadata.is_dir() {
+            return None;
+        }
+
+        let id = (metadata.dev(), metadata.ino());
+        if self.root.one_file_system && id.0 != parent.root_device() {
+            return None;
+        }
+        if parent.ancestors().any(|ancestor| ancestor.id == id) {
+            return None;
+        }
+        Some(Visit {
+            id,
+            through_link: is_link || parent.through_link,
+            parent: Some(parent),
+        })
+    }
+
+    fn push(&self, path: PathBuf, vcs: Option<Vcs>, marker: Option<String>) {
+        if let Some(Vcs::Git(kind)) = vcs {
+            if !self.root.git_kinds.contains(&kind) {
+                return;
+            }
+        }
+        (self.emit)(ScanEvent::Found(Candidate {
+            path,
+            vcs,
+            marker,
+            name: None,
+            session_name: None,
+            session_prefix: self.root.session_prefix.clone(),
+            socket: self.root.socket.clone(),
+        }));
+    }
+
+    /// Reads the ignore files in `dir` if the root respects them, returns
+    /// `None` if there are none.
+    fn read_ignore(&self, dir: &Path) -> Option<Gitignore> {
+        if !self.root.respect_ignore_files {
+            return None;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in IGNORE_FILES {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            found = true;
+            if let Some(source) = builder.add(&path) {
+                (self.emit)(ScanEvent::Warning(Error::InvalidIgnoreFile {
+                    path,
+                    source,
+                }));
+            }
+        }
+        if !found {
+            return None;
+        }
+
+        match builder.build() {
+            Ok(ignore) => Some(ignore),
+            Err(source) => {
+                (self.emit)(ScanEvent::Warning(Error::InvalidIgnoreFile {
+                    path: dir.to_path_buf(),
+                    source,
+                }));
+                None
+            }
+        }
+    }
+}
+
+/// Builds a session name from `prefix` and the last `components` components
+/// of `path`.
+fn session_name(
+    path: &Path,
+    components: usize,
+    prefix: Option<&str>,
+    sanitizer: &Sanitizer,
+) -> Option<String> {
+    let all: Vec<_> = path.components().collect();
+    let start = all.len().saturating_sub(components);
+    let name: PathBuf = all[start..].iter().collect();
+    let name = format!("{}{}", prefix.unwrap_or(""), name.to_str()?);
+    Some(sanitizer.sanitize(&name))
+}
+
+/// Returns the project `query` names: the one with exactly that session name
+/// or name, otherwise the one whose session name, name or path matches
+/// `query` best as a fuzzy pattern. Fails if there is no match or several
+/// equally good ones.
+pub fn find_project<'a>(projects: &'a [Project], query: &str) -> Result<&'a Project, Error> {
+    let exact: Vec<&Project> = projects
+        .iter()
+        .filter(|project| project.session_name == query || project.name.as_deref() == Some(query))
+        .collect();
+    let best = if exact.is_empty() {
+        let matcher = SkimMatcherV2::default();
+        let scored: Vec<(i64, &Project)> = projects
+            .iter()
+            .filter_map(|project| {
+                let score = [project.session_name.clone(), project.label()]
+                    .iter()
+                    .filter_map(|choice| matcher.fuzzy_match(choice, query))
+                    .max()?;
+                Some((score, project))
+            })
+            .collect();
+        let top = scored.iter().map(|(score, _)| *score).max();
+        scored
+            .into_iter()
+            .filter(|(score, _)| Some(*score) == top)
+            .map(|(_, project)| project)
+            .collect()
+    } else {
+        exact
+    };
+
+    match best.as_slice() {
+        [] => Err(Error::NoMatchingProject(query.to_string())),
+        [project] => Ok(project),
+        matches => Err(Error::AmbiguousProject {
+            query: query.to_string(),
+            matches: matches.iter().map(|project| project.label()).collect(),
+        }),
+    }
+}
+
+/// Turns candidates into projects. Projects that would share a session name
+/// get the shortest trailing part of their path that tells them apart, e.g.
+/// `work/api` and `oss/api`, unless their session name was set explicitly.
+pub fn name_projects(
+    found: Vec<Candidate>,
+    sanitizer: &Sanitizer,
+    warnings: &mut Vec<Error>,
+) -> Vec<Project> {
+    let name = |candidate: &Candidate, components| match &candidate.session_name {
+        Some(session_name) => Some(sanitizer.sanitize(session_name)),
+        None => session_name(
+            &candidate.path,
+            components,
+            candidate.session_prefix.as_deref(),
+            sanitizer,
+        ),
+    };
+
+    let mut projects = Vec::new();
+    let mut unnamed = Vec::new();
+    for candidate in found {
+        match name(&candidate, 1) {
+            Some(session_name) => {
+                projects.push(Project {
+                    path: candidate.path.clone(),
+                    name: candidate.name.clone(),
+                    session_name,
+                    vcs: candidate.vcs.clone(),
+                    marker: candidate.marker.clone(),
+                    socket: candidate.socket.clone(),
+                });
+                unnamed.push(candidate);
+            }
+            None => warnings.push(Error::NonUtf8Path(candidate.path)),
+        }
+    }
+
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, project) in projects.iter().enumerate() {
+        groups
+            .entry(project.session_name.clone())
+            .or_default()
+            .push(i);
+    }
+
+    for group in groups.values().filter(|group| group.len() > 1) {
+        for &i in group.iter().filter(|&&i| unnamed[i].session_name.is_none()) {
+            let depth = projects[i].path.components().count();
+            for components in 2..=depth {
+                let candidate = match name(&unnamed[i], components) {
+                    Some(candidate) => candidate,
+                    None => break,
+                };
+                let unique = group
+                    .iter()
+                    .filter(|&&j| j != i)
+                    .all(|&j| name(&unnamed[j], components).as_ref() != Some(&candidate));
+                if unique || components == depth {
+                    projects[i].session_name = candidate;
+                    break;
+                }
+            }
+        }
+    }
+
+    projects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn candidate(path: &str) -> Candidate {
+        Candidate {
+            path: PathBuf::from(path),
+            vcs: None,
+            marker: None,
+            name: None,
+            session_name: None,
+            session_prefix: None,
+            socket: None,
+        }
+    }
+
+    fn session_names(paths: &[&str]) -> Vec<String> {
+        let found = paths.iter().map(|path| candidate(path)).collect();
+        let mut warnings = Vec::new();
+        name_projects(found, &Sanitizer::default(), &mut warnings)
+            .into_iter()
+            .map(|project| project.session_name)
+            .collect()
+    }
+
+    fn project(path: &str, session_name: &str) -> Project {
+        Project {
+            path: PathBuf::from(path),
+            name: None,
+            session_name: session_name.to_string(),
+            vcs: None,
+            marker: None,
+            socket: None,
+        }
+    }
+
+    #[test]
+    fn find_project_prefers_an_exact_session_name() {
+        let projects = [
+            project("/home/me/work/api-gateway", "api-gateway"),
+            project("/home/me/oss/api", "api"),
+        ];
+        let found = find_project(&projects, "api").unwrap();
+        assert_eq!(found.path, Path::new("/home/me/oss/api"));
+    }
+
+    #[test]
+    fn find_project_matches_fuzzily() {
+        let projects = [
+            project("/home/me/work/api", "api"),
+            project("/home/me/oss/website", "website"),
+        ];
+        let found = find_project(&projects, "wbst").unwrap();
+        assert_eq!(found.session_name, "website");
+    }
+
+    #[test]
+    fn find_project_rejects_ties() {
+        let projects = [
+            project("/home/me/work/api", "work/api"),
+            project("/home/me/oss/api", "oss/api"),
+        ];
+        match find_project(&projects, "api") {
+            Err(Error::AmbiguousProject { query, matches }) => {
+                assert_eq!(query, "api");
+                assert_eq!(matches, ["/home/me/work/api", "/home/me/oss/api"]);
+            }
+            other => panic!("expected AmbiguousProject, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn find_project_fails_without_a_match() {
+        let projects = [project("/home/me/work/api", "api")];
+        match find_project(&projects, "zzz") {
+            Err(Error::NoMatchingProject(query)) => assert_eq!(query, "zzz"),
+            other => panic!("expected NoMatchingProject, got {:?}", other),
+        }
+    }
+
+    /// Reports `events` as found by the given roots of `/r/a` and `/r/b`,
+    /// returning the collected paths with the root that reported them.
+    fn claimed(events: Vec<(Option<usize>, ScanEvent)>) -> Vec<(String, String)> {
+        let claims = Claims::new(&[PathBuf::from("/r/a"), PathBuf::from("/r/b")]);
+        let collected = Mutex::new(Collected::default());
+        let send = |event| {
+            if let ScanEvent::Found(candidate) = event {
+                collected.lock().unwrap().push(candidate);
+            }
+        };
+        for (root, event) in events {
+            claims.report(root, event, &send);
+        }
+        collected
+            .into_inner()
+            .unwrap()
+            .candidates
+            .into_iter()
+            .map(|candidate| {
+                (
+                    candidate.path.display().to_string(),
+                    candidate.session_prefix.unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    fn found(path: &str, by: &str) -> ScanEvent {
+        ScanEvent::Found(Candidate {
+            session_prefix: Some(by.to_string()),
+            ..candidate(path)
+        })
+    }
+
+    fn claim(path: &str, by: &str) -> (String, String) {
+        (path.to_string(), by.to_string())
+    }
+
+    #[test]
+    fn claims_go_to_the_owning_root() {
+        assert_eq!(
+            claimed(vec![
+                (Some(0), found("/r/b/x", "a")),
+                (Some(1), found("/r/b/x", "b")),
+                (Some(0), found("/r/a/y", "a")),
+                (Some(1), found("/r/a/y", "b")),
+            ]),
+            [claim("/r/b/x", "b"), claim("/r/a/y", "a")]
+        );
+    }
+
+    #[test]
+    fn claims_outside_the_roots_go_to_the_first_root() {
+        assert_eq!(
+            claimed(vec![
+                (Some(1), found("/o/x", "b")),
+                (Some(0), found("/o/x", "a")),
+            ]),
+            [claim("/o/x", "a")]
+        );
+        assert_eq!(
+            claimed(vec![
+                (Some(0), found("/o/x", "a")),
+                (Some(1), found("/o/x", "b")),
+            ]),
+            [claim("/o/x", "a")]
+        );
+    }
+
+    #[test]
+    fn claims_keep_projects_of_roots_that_never_finish() {
+        // `/r/b` times out before finding the project it owns
+        assert_eq!(
+            claimed(vec![
+                (Some(0), found("/r/b/x", "a")),
+                (Some(0), ScanEvent::Finished(PathBuf::from("/r/a"))),
+            ]),
+            [claim("/r/b/x", "a")]
+        );
+    }
+
+    #[test]
+    fn claims_list_static_entries_as_configured() {
+        let dir = env::temp_dir().join(format!("tmux-sessionizer-claims-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("data/notes")).unwrap();
+        std::os::unix::fs::symlink(dir.join("data/notes"), dir.join("notes")).unwrap();
+
+        let claims = Claims::new(&[dir.join("data")]);
+        let collected = Mutex::new(Collected::default());
+        let send = |event| {
+            if let ScanEvent::Found(candidate) = event {
+                collected.lock().unwrap().push(candidate);
+            }
+        };
+        let entry = dir.join("notes").display().to_string();
+        let walked = dir.join("data/notes").display().to_string();
+        claims.report(None, found(&entry, "entry"), &send);
+        claims.report(Some(0), found(&walked, "data"), &send);
+        let candidates = collected.into_inner().unwrap().candidates;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].path, Path::new(&entry));
+    }
+
+    #[test]
+    fn claims_prefer_static_entries() {
+        assert_eq!(
+            claimed(vec![
+                (None, found("/r/a/x", "entry")),
+                (Some(0), found("/r/a/x", "a")),
+            ]),
+            [claim("/r/a/x", "entry")]
+        );
+    }
+
+    #[test]
+    fn name_projects_keeps_unique_basenames() {
+        assert_eq!(
+            session_names(&["/home/me/work/api", "/home/me/oss/web"]),
+            ["api", "web"]
+        );
+    }
+
+    #[test]
+    fn name_projects_disambiguates_shared_basenames() {
+        assert_eq!(
+            session_names(&["/home/me/work/api", "/home/me/oss/api"]),
+            ["work/api", "oss/api"]
+        );
+    }
+
+    #[test]
+    fn name_projects_uses_as_many_components_as_needed() {
+        assert_eq!(
+            session_names(&["/home/me/a/x/api", "/home/me/b/x/api", "/home/me/web"]),
+            ["a/x/api", "b/x/api", "web"]
+        );
+    }
+
+    #[test]
+    fn name_projects_keeps_explicit_session_names() {
+        let mut explicit = candidate("/etc/nixos");
+        explicit.session_name = Some("api".to_string());
+        let found = vec![explicit, candidate("/home/me/work/api")];
+        let mut warnings = Vec::new();
+        let names: Vec<String> = name_projects(found, &Sanitizer::default(), &mut warnings)
+            .into_iter()
+            .map(|project| project.session_name)
+            .collect();
+        assert_eq!(names, ["api", "work/api"]);
+    }
+}
@@ -0,0 +1,100 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors returned by this crate.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to read configuration file {}: {source}", path.display())]
+    ConfigRead { path: PathBuf, source: io::Error },
+
+    #[error("failed to parse configuration file {}: {message}", path.display())]
+    ConfigParse {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+
+    #[error("invalid session_name_replacement {0:?}: only ASCII letters, digits, `-`, `_` and `/` are allowed in session names")]
+    InvalidReplacement(char),
+
+    #[error("invalid exclude pattern `{pattern}`: {source}")]
+    InvalidPattern {
+        pattern: String,
+        source: globset::Error,
+    },
+
+    #[error("failed to read ignore file {}: {source}", path.display())]
+    InvalidIgnoreFile {
+        path: PathBuf,
+        source: ignore::Error,
+    },
+
+    #[error("search path does not exist: {}", .0.display())]
+    MissingSearchPath(PathBuf),
+
+    #[error("entry does not exist: {}", .0.display())]
+    MissingEntry(PathBuf),
+
+    #[error("search path did not finish within {}s, its projects may be incomplete: {}", timeout.as_secs(), path.display())]
+    ScanTimeout { path: PathBuf, timeout: Duration },
+
+    #[error("failed to write cache {}: {source}", path.display())]
+    Cache { path: PathBuf, source: io::Error },
+
+    #[error("failed to read directory {}: {source}", path.display())]
+    UnreadableDirectory { path: PathBuf, source: io::Error },
+
+    #[error("terminal error: {source}")]
+    Terminal { source: io::Error },
+
+    #[error("failed to write output: {source}")]
+    Output { source: io::Error },
+
+    #[error("not a directory: {}", .0.display())]
+    NotADirectory(PathBuf),
+
+    #[error("no project matches `{0}`")]
+    NoMatchingProject(String),
+
+    #[error("`{query}` matches several projects: {}", matches.join(", "))]
+    AmbiguousProject { query: String, matches: Vec<String> },
+
+    #[error("path is not valid UTF-8: {}", .0.display())]
+    NonUtf8Path(PathBuf),
+
+    #[error("`{0}` was not found on PATH")]
+    MissingBinary(String),
+
+    #[error("failed to run `{program}`: {source}")]
+    Command { program: String, source: io::Error },
+
+    #[error("`{program}` exited with {status}")]
+    CommandFailed { program: String, status: ExitStatus },
+}
+
+impl Error {
+    pub fn config_parse(path: PathBuf, err: serde_yaml::Error) -> Self {
+        let location = err.location();
+        Error::ConfigParse {
+            path,
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message: err.to_string(),
+        }
+    }
+
+    pub fn command(program: &str, source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::NotFound {
+            Error::MissingBinary(program.to_string())
+        } else {
+            Error::Command {
+                program: program.to_string(),
+                source,
+            }
+        }
+    }
+}
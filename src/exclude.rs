@@ -0,0 +1,150 @@
+use crate::error::Error;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+
+/// Directories that are never worth descending into, pruned unless
+/// `default_excludes` is disabled.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    ".cache",
+    ".cargo",
+    ".direnv",
+    ".gradle",
+    ".m2",
+    ".npm",
+    ".rustup",
+    ".tox",
+    ".venv",
+    "__pycache__",
+    "node_modules",
+    "target",
+    "venv",
+];
+
+/// Glob patterns deciding which directories the walker does not descend
+/// into. Patterns without a `/` match directory names anywhere, absolute
+/// patterns (after `~` expansion) match full paths and all other patterns
+/// match paths relative to the search root.
+#[derive(Debug, Clone)]
+pub struct Exclude {
+    root: PathBuf,
+    names: GlobSet,
+    absolute: GlobSet,
+    relative: GlobSet,
+}
+
+impl Exclude {
+    pub fn new<'a, I>(root: &Path, patterns: I) -> Result<Exclude, Error>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut names = GlobSetBuilder::new();
+        let mut absolute = GlobSetBuilder::new();
+        let mut relative = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let expanded = shellexpand::tilde(pattern);
+            let trimmed = expanded.trim_end_matches('/');
+            let glob = glob(trimmed).map_err(|source| Error::InvalidPattern {
+                pattern: pattern.to_string(),
+                source,
+            })?;
+            if !trimmed.contains('/') {
+                names.add(glob);
+            } else if Path::new(trimmed).is_absolute() {
+                absolute.add(glob);
+            } else {
+                relative.add(glob);
+            }
+        }
+
+        let build = |builder: GlobSetBuilder| {
+            builder.build().map_err(|source| Error::InvalidPattern {
+                pattern: source.glob().unwrap_or_default().to_string(),
+                source,
+            })
+        };
+        Ok(Exclude {
+            root: root.to_path_buf(),
+            names: build(names)?,
+            absolute: build(absolute)?,
+            relative: build(relative)?,
+        })
+    }
+
+    /// Returns whether the directory at `path` is excluded.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if path
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name))
+        {
+            return true;
+        }
+        if self.absolute.is_match(path) {
+            return true;
+        }
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => self.relative.is_match(relative),
+            Err(_) => false,
+        }
+    }
+}
+
+fn glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exclude(patterns: &[&str]) -> Exclude {
+        Exclude::new(Path::new("/home/me/src"), patterns.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn names_match_anywhere() {
+        let exclude = exclude(&["node_modules", "*.egg-info"]);
+        assert!(exclude.is_excluded(Path::new("/home/me/src/web/node_modules")));
+        assert!(exclude.is_excluded(Path::new("/elsewhere/node_modules")));
+        assert!(exclude.is_excluded(Path::new("/home/me/src/py/foo.egg-info")));
+        assert!(!exclude.is_excluded(Path::new("/home/me/src/node_modules/web")));
+    }
+
+    #[test]
+    fn absolute_patterns_match_full_paths() {
+        let exclude = exclude(&["/home/me/src/vendor/*", "~/Library"]);
+        assert!(exclude.is_excluded(Path::new("/home/me/src/vendor/lib")));
+        assert!(!exclude.is_excluded(Path::new("/home/me/src/vendor/lib/deep")));
+        assert!(!exclude.is_excluded(Path::new("/home/me/src/vendor")));
+
+        let home = shellexpand::tilde("~").to_string();
+        assert!(exclude.is_excluded(&Path::new(&home).join("Library")));
+    }
+
+    #[test]
+    fn other_patterns_match_relative_to_the_root() {
+        let exclude = exclude(&["archive/*", "**/build/out"]);
+        assert!(exclude.is_excluded(Path::new("/home/me/src/archive/old")));
+        assert!(!exclude.is_excluded(Path::new("/home/me/src/work/archive/old")));
+        assert!(!exclude.is_excluded(Path::new("/elsewhere/archive/old")));
+        assert!(exclude.is_excluded(Path::new("/home/me/src/a/b/build/out")));
+        assert!(exclude.is_excluded(Path::new("/home/me/src/build/out")));
+    }
+
+    #[test]
+    fn trailing_slashes_are_ignored() {
+        let exclude = exclude(&["target/", "archive/old/", "/tmp/scratch/"]);
+        assert!(exclude.is_excluded(Path::new("/home/me/src/api/target")));
+        assert!(exclude.is_excluded(Path::new("/home/me/src/archive/old")));
+        assert!(exclude.is_excluded(Path::new("/tmp/scratch")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let err = Exclude::new(Path::new("/"), ["ok", "[unclosed"]).unwrap_err();
+        match err {
+            Error::InvalidPattern { pattern, .. } => assert_eq!(pattern, "[unclosed"),
+            other => panic!("expected InvalidPattern, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Discover projects on disk and open them as tmux sessions.
+//!
+//! The typical flow mirrors the `tmux-sessionizer-rs` binary:
+//!
+//! ```no_run
+//! use tmux_sessionizer::{discovery, Config, SessionManager};
+//!
+//! let (config, _) = Config::load(None)?;
+//! let found = discovery::discover(&config)?;
+//! if let Some(project) = found.projects.first() {
+//!     SessionManager::new().open(project)?;
+//! }
+//! # Ok::<(), tmux_sessionizer::Error>(())
+//! ```
+
+pub mod cache;
+pub mod config;
+pub mod discovery;
+pub mod error;
+pub mod exclude;
+pub mod picker;
+pub mod session;
+pub mod vcs;
+
+pub use config::Config;
+pub use discovery::{Discovery, Project};
+pub use error::Error;
+pub use session::SessionManager;
@@ -1,11 +1,20 @@
-use path_clean::PathClean;
-use rayon::prelude::*;
-use serde::Deserialize;
+use serde::Serialize;
 use std::env;
-use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
 use structopt::StructOpt;
+use tmux_sessionizer::cache::{self, Cache};
+use tmux_sessionizer::config::{Config, Picker};
+use tmux_sessionizer::discovery::{self, Candidate, Collected, Project, ScanEvent, Scanner};
+use tmux_sessionizer::session::{self, Sanitizer};
+use tmux_sessionizer::vcs::{Detector, GitKind, Vcs};
+use tmux_sessionizer::{picker, Error, SessionManager};
 
 #[derive(Debug, StructOpt)]
 struct Cli {
@@ -13,190 +22,326 @@ struct Cli {
         short,
         long,
         parse(from_os_str),
-        help = "Path to YAML configuration file"
+        help = "Path to YAML configuration file (defaults to $TMUX_SESSIONIZER_CONFIG, then $XDG_CONFIG_HOME/tmux-sessionizer/config.yaml)"
     )]
     config: Option<PathBuf>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Config {
-    search_paths: Vec<Option<String>>,
-    nested: Option<bool>,
-}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::from_args();
-    let config = load_config(args.config);
-    let nested = config.nested.unwrap_or(false);
-
-    let search_paths = filter_contained_paths(config.search_paths);
-
-    let repos: Vec<PathBuf> = search_paths
-        .par_iter()
-        .filter_map(|root| {
-            if root.exists() {
-                Some(find_git_repos(root, nested))
-            } else {
-                eprintln!("Path does not exist: {}", root.display());
-                None
-            }
-        })
-        .flatten()
-        .collect();
+    #[structopt(
+        short = "L",
+        long,
+        help = "Name of the tmux server socket, like `tmux -L`. Used for every project, overriding all configured sockets"
+    )]
+    socket_name: Option<String>,
 
-    let choices = repos
-        .iter()
-        .map(|p| p.display().to_string())
-        .collect::<Vec<_>>();
-    let selected = fzf_select(&choices)?;
+    #[structopt(
+        short = "S",
+        long,
+        conflicts_with = "socket-name",
+        help = "Path of the tmux server socket, like `tmux -S`. Used for every project, overriding all configured sockets"
+    )]
+    socket_path: Option<String>,
 
-    if selected.is_empty() {
-        return Ok(());
-    }
+    #[structopt(
+        long,
+        help = "Ignore the cached projects and scan the search paths again"
+    )]
+    refresh: bool,
 
-    let selected_path = Path::new(&selected);
-    let selected_name = selected_path
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .replace('.', "_");
+    /// Scans the search paths and stores the result in the cache without
+    /// opening the picker, used for refreshing the cache in the background.
+    #[structopt(long, hidden = true)]
+    update_cache: bool,
 
-    if !is_tmux_running() {
-        start_tmux_session(&selected_name, &selected_path)?;
-    }
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
 
-    switch_tmux_client(&selected_name, &selected_path)?;
+#[derive(Debug, StructOpt)]
+enum Subcommand {
+    #[structopt(about = "Print the discovered projects instead of opening the picker")]
+    List {
+        #[structopt(
+            short = "0",
+            long,
+            help = "Separate paths with NUL instead of newline characters"
+        )]
+        null: bool,
+
+        #[structopt(
+            long,
+            conflicts_with = "null",
+            help = "Print a JSON array of objects with the path, session name, VCS, kind of git checkout and whether the session is running"
+        )]
+        json: bool,
+    },
+
+    #[structopt(about = "Open a project without the picker")]
+    Open {
+        #[structopt(
+            help = "Path of the directory to open, or the name of a discovered project, matched fuzzily"
+        )]
+        target: String,
+    },
+}
 
-    Ok(())
+/// A project as printed by `list --json`.
+#[derive(Debug, Serialize)]
+struct ListEntry<'a> {
+    path: &'a Path,
+    session_name: &'a str,
+    vcs: Option<String>,
+    /// Shape of a git checkout, `None` for other projects.
+    git_kind: Option<GitKind>,
+    running: bool,
 }
 
-fn load_config(config_path: Option<PathBuf>) -> Config {
-    if let Some(path) = config_path {
-        let config_content = fs::read_to_string(path).expect("Failed to read configuration file");
-        serde_yaml::from_str(&config_content).expect("Failed to parse configuration file")
-    } else {
-        eprintln!("Configuration file is required.");
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
         std::process::exit(1);
     }
 }
 
-fn filter_contained_paths(paths: Vec<Option<String>>) -> Vec<PathBuf> {
-    let mut expanded_cleaned_paths: Vec<PathBuf> = paths
-        .into_iter()
-        .filter_map(|item| item.map(PathBuf::from))
-        .map(|p| shellexpand::tilde(p.to_str().unwrap()).to_string())
-        .map(|p| PathBuf::from(p).clean())
-        .collect();
-
-
-    expanded_cleaned_paths.sort();
-    expanded_cleaned_paths.dedup();
+fn run() -> Result<(), Error> {
+    let args = Cli::from_args();
+    let (mut config, config_path) = Config::load(args.config.clone())?;
+    match &config_path {
+        Some(path) => eprintln!("Using configuration file: {}", path.display()),
+        None => eprintln!("No configuration file found, using built-in defaults."),
+    }
+    if args.socket_name.is_some() || args.socket_path.is_some() {
+        config.override_socket(args.socket_name.clone(), args.socket_path.clone());
+    }
 
-    let mut result = Vec::new();
+    let scanner = Scanner::new(&config)?;
+    let cache = Cache::for_config(&config);
 
-    for path in &expanded_cleaned_paths {
-        if !expanded_cleaned_paths
-            .iter()
-            .any(|other| other != path && path.starts_with(other))
-        {
-            result.push(path.clone());
+    if args.update_cache {
+        if let Some(cache) = &cache {
+            print_warnings(&cache.update(&scanner)?);
         }
+        return Ok(());
     }
 
-    result
-}
-
-fn find_git_repos(root: &Path, nested: bool) -> Vec<PathBuf> {
-    if !root.is_dir() {
-        return Vec::new();
+    if let Some(command) = &args.command {
+        let served = cache::candidates(cache.as_ref(), &scanner, args.refresh);
+        if served.needs_refresh {
+            refresh_in_background(config_path.as_ref(), &args);
+        }
+        print_warnings(&served.warnings);
+        let projects = name_projects(served.candidates, &config);
+        return match command {
+            Subcommand::List { null, json } => list(&config, &projects, *null, *json),
+            Subcommand::Open { target } => open(&config, &projects, target),
+        };
     }
 
-    let mut git_repos = Vec::new();
-
-    let entries: Vec<_> = fs::read_dir(root).unwrap().filter_map(Result::ok).collect();
-    for entry in entries {
-        let path = entry.path();
-        if !path.is_dir() {
-            continue;
+    let cached = cache
+        .as_ref()
+        .filter(|_| !args.refresh)
+        .and_then(Cache::candidates);
+    let (selected, found, warnings) = match cached {
+        Some((found, stale)) => {
+            if stale {
+                refresh_in_background(config_path.as_ref(), &args);
+            }
+            let labels: Vec<String> = found.iter().map(Candidate::label).collect();
+            (
+                picker::select(
+                    config.picker.unwrap_or(Picker::Fzf),
+                    labels,
+                    mpsc::channel().1,
+                ),
+                found,
+                Vec::new(),
+            )
         }
-        if path.join(".git").exists() {
-            git_repos.push(path.clone());
-            if nested {
-                git_repos.extend(find_git_repos(&path, nested));
+        None => {
+            let (selected, mut collected) = scan_into_picker(&config, &scanner);
+            match cache.as_ref().map(|cache| cache.store_scan(&collected)) {
+                // the picker was closed before the scan finished
+                Some(Ok(true)) => refresh_in_background(config_path.as_ref(), &args),
+                Some(Err(err)) => collected.warnings.push(err),
+                Some(Ok(false)) | None => {}
             }
-            continue;
+            (selected, collected.candidates, collected.warnings)
+        }
+    };
+    print_warnings(&warnings);
+    let projects = name_projects(found, &config);
+
+    let selected = match selected? {
+        Some(selected) => selected,
+        None => return Ok(()),
+    };
+    let project = match projects.iter().find(|project| project.label() == selected) {
+        Some(project) => project,
+        None => return Ok(()),
+    };
+
+    // fzf could not show the timeouts, and tmux takes over the terminal next
+    if config.picker.unwrap_or(Picker::Fzf) == Picker::Fzf {
+        let timeouts: Vec<String> = warnings
+            .iter()
+            .filter(|warning| matches!(warning, Error::ScanTimeout { .. }))
+            .map(ToString::to_string)
+            .collect();
+        if !timeouts.is_empty() {
+            let _ = session::display_message(&format!("Warning: {}", timeouts.join("; ")));
         }
-
-        git_repos.extend(find_git_repos(&path, nested));
     }
 
-    git_repos
+    SessionManager::from_config(&config).open(project)
 }
 
-fn fzf_select(choices: &[String]) -> Result<String, Box<dyn std::error::Error>> {
-    use std::io::Write;
+/// Names the candidates, printing the warnings.
+fn name_projects(found: Vec<Candidate>, config: &Config) -> Vec<Project> {
+    let mut warnings = Vec::new();
+    let projects = discovery::name_projects(found, &Sanitizer::from_config(config), &mut warnings);
+    print_warnings(&warnings);
+    projects
+}
 
-    let mut child = Command::new("fzf")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
+/// Prints `projects` to stdout for the `list` subcommand.
+fn list(config: &Config, projects: &[Project], null: bool, json: bool) -> Result<(), Error> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let written = if json {
+        let sessions = SessionManager::from_config(config).resolve_session_names(projects)?;
+        let entries: Vec<ListEntry> = projects
+            .iter()
+            .zip(&sessions)
+            .map(|(project, (session_name, running))| ListEntry {
+                path: &project.path,
+                session_name,
+                vcs: project.vcs.as_ref().map(ToString::to_string),
+                git_kind: match project.vcs {
+                    Some(Vcs::Git(kind)) => Some(kind),
+                    _ => None,
+                },
+                running: *running,
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut stdout, &entries)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(stdout))
+    } else {
+        let separator = if null { b'\0' } else { b'\n' };
+        projects.iter().try_for_each(|project| {
+            stdout.write_all(project.path.as_os_str().as_bytes())?;
+            stdout.write_all(&[separator])
+        })
+    };
 
-    {
-        let stdin = child.stdin.as_mut().ok_or("Failed to open stdin")?;
-        for choice in choices {
-            writeln!(stdin, "{}", choice)?;
-        }
+    match written.and_then(|()| stdout.flush()) {
+        // the reader went away, e.g. `list | head`
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|err| Error::Output { source: err }),
     }
-
-    let output = child.wait_with_output()?;
-    let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    Ok(selected)
 }
 
-fn is_tmux_running() -> bool {
-    env::var("TMUX").is_ok()
-        || Command::new("pgrep")
-            .arg("tmux")
-            .output()
-            .map_or(false, |o| o.status.success())
+/// Opens `target` for the `open` subcommand. A target that looks like a path,
+/// because it contains a `/` or starts with `.` or `~`, has to be an existing
+/// directory and is opened as the project found there, or as a project of its
+/// own if there is none. Anything else is looked up by name.
+fn open(config: &Config, projects: &[Project], target: &str) -> Result<(), Error> {
+    let project = if target.contains('/') || target.starts_with('.') || target.starts_with('~') {
+        let path = PathBuf::from(shellexpand::tilde(target).as_ref());
+        if !path.is_dir() {
+            return Err(Error::NotADirectory(path));
+        }
+        // found projects are listed under their canonical path, static entries
+        // as configured
+        let path = path.canonicalize().unwrap_or(path);
+        let is_target = |project: &&Project| {
+            project.path == path
+                || project
+                    .path
+                    .canonicalize()
+                    .is_ok_and(|project| project == path)
+        };
+        match projects.iter().find(is_target) {
+            Some(project) => project.clone(),
+            None => {
+                let vcs = Detector::from_config(config).detect(&path);
+                Project::new(path, vcs, &Sanitizer::from_config(config))?
+            }
+        }
+    } else {
+        discovery::find_project(projects, target)?.clone()
+    };
+
+    SessionManager::from_config(config).open(&project)
 }
 
-fn start_tmux_session(session_name: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    Command::new("tmux")
-        .arg("new-session")
-        .arg("-s")
-        .arg(session_name)
-        .arg("-c")
-        .arg(path)
-        .status()?;
-    Ok(())
+/// Streams the scan into the picker, returning the selection and what the
+/// scan found until then.
+fn scan_into_picker(
+    config: &Config,
+    scanner: &Scanner,
+) -> (Result<Option<String>, Error>, Collected) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let scan = scanner.spawn(Arc::clone(&cancel));
+
+    // forward labels to the picker as they arrive while keeping the candidates,
+    // the picker sees the end of the list once the scan finishes or times out
+    let (labels, picker_labels) = mpsc::channel();
+    let (notices, picker_notices) = mpsc::channel();
+    let collect = thread::spawn(move || {
+        scan.collect_with(|event| match event {
+            ScanEvent::Found(candidate) => {
+                let _ = labels.send(candidate.label());
+            }
+            ScanEvent::Warning(warning @ Error::ScanTimeout { .. }) => {
+                let _ = notices.send(format!("Warning: {}", warning));
+            }
+            _ => {}
+        })
+    });
+
+    let selected = picker::select(
+        config.picker.unwrap_or(Picker::Fzf),
+        picker_labels,
+        picker_notices,
+    );
+    cancel.store(true, Ordering::Relaxed);
+    let mut collected = collect.join().unwrap_or_default();
+    scanner.sort(&mut collected.candidates);
+    (selected, collected)
 }
 
-fn switch_tmux_client(session_name: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let has_session = Command::new("tmux")
-        .arg("has-session")
-        .arg("-t")
-        .arg(session_name)
-        .output()?
-        .status
-        .success();
-
-    if !has_session {
-        Command::new("tmux")
-            .arg("new-session")
-            .arg("-ds")
-            .arg(session_name)
-            .arg("-c")
-            .arg(path)
-            .status()?;
+fn print_warnings(warnings: &[Error]) {
+    for warning in warnings {
+        match warning {
+            // nothing was skipped, the scan stopped early or the cache was not written
+            Error::Cache { .. } | Error::ScanTimeout { .. } => eprintln!("Warning: {}", warning),
+            _ => eprintln!("Warning: {}, skipping", warning),
+        }
     }
+}
 
-    Command::new("tmux")
-        .arg("switch-client")
-        .arg("-t")
-        .arg(session_name)
-        .status()?;
-
-    Ok(())
+/// Runs `--update-cache` with the same configuration in a detached process,
+/// so the refresh outlives the tmux client we are about to attach.
+fn refresh_in_background(config_path: Option<&PathBuf>, args: &Cli) {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(_) => return,
+    };
+    let mut command = Command::new(exe);
+    command.arg("--update-cache");
+    if let Some(path) = config_path {
+        command.arg("--config").arg(path);
+    }
+    if let Some(name) = &args.socket_name {
+        command.arg("--socket-name").arg(name);
+    }
+    if let Some(path) = &args.socket_path {
+        command.arg("--socket-path").arg(path);
+    }
+    let _ = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn();
 }
@@ -0,0 +1,478 @@
+use crate::config::Picker;
+use crate::error::Error;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, SetAttribute, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use std::io::{self, Stderr, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Lets the user pick one of `choices` with `picker`, see [`fzf_select`]
+/// and [`builtin_select`]. `notices`, such as search paths that did not
+/// finish in time, are shown above the choices by the built-in finder as
+/// they arrive. fzf cannot change its header once open, so they are left to
+/// the caller there.
+pub fn select<I>(
+    picker: Picker,
+    choices: I,
+    notices: Receiver<String>,
+) -> Result<Option<String>, Error>
+where
+    I: IntoIterator<Item = String>,
+    I::IntoIter: Send + 'static,
+{
+    match picker {
+        Picker::Fzf => fzf_select(choices),
+        Picker::Builtin => builtin_select(choices, notices),
+    }
+}
+
+/// Lets the user pick one of `choices` with `fzf`. Returns `None` if the
+/// selection was aborted.
+///
+/// `choices` is consumed on a separate thread while fzf is already open, so
+/// it can be a channel that is still being filled by a running scan.
+pub fn fzf_select<I>(choices: I) -> Result<Option<String>, Error>
+where
+    I: IntoIterator<Item = String>,
+    I::IntoIter: Send + 'static,
+{
+    let mut child = Command::new("fzf")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::command("fzf", err))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let choices = choices.into_iter();
+        thread::spawn(move || {
+            for choice in choices {
+                // fzf closes its stdin once a choice is made, so a broken pipe is not an error
+                if writeln!(stdin, "{}", choice).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| Error::command("fzf", err))?;
+    let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if selected.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(selected))
+    }
+}
+
+/// Lets the user pick one of `choices` with the built-in fuzzy finder.
+/// Returns `None` if the selection was aborted.
+///
+/// Like with [`fzf_select`], `choices` is consumed while the finder is
+/// already open, and so is `notices`, which are shown below the prompt. The
+/// finder is drawn on stderr, typing filters the choices,
+/// the arrow keys, `Ctrl-P`/`Ctrl-N` and `PageUp`/`PageDown` move the
+/// selection, `Enter` accepts it and `Esc` or `Ctrl-C` aborts.
+pub fn builtin_select<I>(choices: I, notices: Receiver<String>) -> Result<Option<String>, Error>
+where
+    I: IntoIterator<Item = String>,
+    I::IntoIter: Send + 'static,
+{
+    let (sender, received) = mpsc::channel();
+    let choices = choices.into_iter();
+    thread::spawn(move || {
+        for choice in choices {
+            if sender.send(choice).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut screen = Screen::open().map_err(|source| Error::Terminal { source })?;
+    let mut finder = Finder::default();
+    let mut receiving = true;
+    let mut dirty = true;
+    loop {
+        let mut arrived = false;
+        while receiving {
+            match received.try_recv() {
+                Ok(choice) => {
+                    finder.choices.push(choice);
+                    arrived = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    receiving = false;
+                    dirty = true;
+                }
+            }
+        }
+        if arrived {
+            finder.filter();
+            dirty = true;
+        }
+        for notice in notices.try_iter() {
+            finder.notices.push(notice);
+            dirty = true;
+        }
+        if dirty {
+            screen
+                .draw(&mut finder, receiving)
+                .map_err(|source| Error::Terminal { source })?;
+            dirty = false;
+        }
+
+        // wake up regularly while choices are still arriving
+        let timeout = Duration::from_millis(if receiving { 50 } else { 500 });
+        if !event::poll(timeout).map_err(|source| Error::Terminal { source })? {
+            continue;
+        }
+        match event::read().map_err(|source| Error::Terminal { source })? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => {
+                match finder.handle(key, screen.list_height(&finder)) {
+                    Action::Continue => dirty = true,
+                    Action::Accept => return Ok(finder.selected().map(str::to_string)),
+                    Action::Abort => return Ok(None),
+                }
+            }
+            Event::Resize(..) => dirty = true,
+            _ => {}
+        }
+    }
+}
+
+enum Action {
+    Continue,
+    Accept,
+    Abort,
+}
+
+/// State of the built-in fuzzy finder.
+#[derive(Default)]
+struct Finder {
+    choices: Vec<String>,
+    query: String,
+    /// Indices of the choices matching the query, best match first, with
+    /// the positions of the matched characters.
+    matches: Vec<(usize, Vec<usize>)>,
+    /// Index into `matches` of the selected choice.
+    cursor: usize,
+    /// Index into `matches` of the first visible choice.
+    scroll: usize,
+    /// Messages shown between the counter and the choices.
+    notices: Vec<String>,
+    matcher: SkimMatcherV2,
+}
+
+impl Finder {
+    /// Matches the choices against the query. Equally good matches keep the
+    /// order they arrived in.
+    fn filter(&mut self) {
+        if self.query.is_empty() {
+            self.matches = (0..self.choices.len()).map(|i| (i, Vec::new())).collect();
+        } else {
+            let mut scored: Vec<(i64, usize, Vec<usize>)> = self
+                .choices
+                .iter()
+                .enumerate()
+                .filter_map(|(i, choice)| {
+                    let (score, indices) = self.matcher.fuzzy_indices(choice, &self.query)?;
+                    Some((score, i, indices))
+                })
+                .collect();
+            scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+            self.matches = scored
+                .into_iter()
+                .map(|(_, i, indices)| (i, indices))
+                .collect();
+        }
+        self.cursor = self.cursor.min(self.matches.len().saturating_sub(1));
+    }
+
+    fn selected(&self) -> Option<&str> {
+        let (i, _) = self.matches.get(self.cursor)?;
+        Some(&self.choices[*i])
+    }
+
+    fn handle(&mut self, key: KeyEvent, page: usize) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Esc => return Action::Abort,
+            KeyCode::Char('c' | 'g' | 'q') if ctrl => return Action::Abort,
+            KeyCode::Up => self.move_cursor(-1),
+            KeyCode::Char('p' | 'k') if ctrl => self.move_cursor(-1),
+            KeyCode::Down | KeyCode::Tab => self.move_cursor(1),
+            KeyCode::Char('n' | 'j') if ctrl => self.move_cursor(1),
+            KeyCode::PageUp => self.move_cursor(-(page as isize)),
+            KeyCode::PageDown => self.move_cursor(page as isize),
+            KeyCode::Char('u') if ctrl => self.edit(String::clear),
+            KeyCode::Char('w') if ctrl => self.edit(|query| {
+                let end = query.trim_end().len();
+                let start = query[..end].rfind(' ').map_or(0, |i| i + 1);
+                query.truncate(start);
+            }),
+            KeyCode::Backspace => self.edit(|query| {
+                query.pop();
+            }),
+            KeyCode::Char(c) if !ctrl => self.edit(|query| query.push(c)),
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn move_cursor(&mut self, by: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(by).min(last);
+    }
+
+    /// Changes the query and starts over at the best match.
+    fn edit(&mut self, change: impl FnOnce(&mut String)) {
+        let before = self.query.clone();
+        change(&mut self.query);
+        if self.query != before {
+            self.cursor = 0;
+            self.filter();
+        }
+    }
+}
+
+/// The terminal while the finder is open: raw mode on the alternate screen,
+/// restored when dropped.
+struct Screen {
+    out: Stderr,
+}
+
+impl Screen {
+    fn open() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stderr();
+        if let Err(err) = queue!(out, terminal::EnterAlternateScreen).and_then(|()| out.flush()) {
+            let _ = terminal::disable_raw_mode();
+            return Err(err);
+        }
+        Ok(Screen { out })
+    }
+
+    /// Number of rows available for choices, below the prompt, the counter
+    /// and the notices of `finder`.
+    fn list_height(&self, finder: &Finder) -> usize {
+        let (_, rows) = terminal::size().unwrap_or((80, 24));
+        usize::from(rows)
+            .saturating_sub(2 + finder.notices.len())
+            .max(1)
+    }
+
+    fn draw(&mut self, finder: &mut Finder, receiving: bool) -> io::Result<()> {
+        let (columns, _) = terminal::size()?;
+        let width = usize::from(columns);
+        let height = self.list_height(finder);
+        let top = 2 + finder.notices.len();
+        // keep the selection visible
+        if finder.cursor < finder.scroll {
+            finder.scroll = finder.cursor;
+        } else if finder.cursor >= finder.scroll + height {
+            finder.scroll = finder.cursor + 1 - height;
+        }
+        let scroll = finder.scroll;
+
+        queue!(
+            self.out,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        for (row, (i, indices)) in finder.matches.iter().enumerate().skip(scroll).take(height) {
+            let selected = row == finder.cursor;
+            queue!(self.out, cursor::MoveTo(0, (row - scroll + top) as u16))?;
+            if selected {
+                queue!(
+                    self.out,
+                    SetForegroundColor(Color::Red),
+                    Print("> "),
+                    SetAttribute(Attribute::Bold)
+                )?;
+            } else {
+                queue!(self.out, Print("  "))?;
+            }
+            let chars = finder.choices[*i].chars().take(width.saturating_sub(2));
+            for (position, c) in chars.enumerate() {
+                let color = if indices.contains(&position) {
+                    Color::Green
+                } else {
+                    Color::Reset
+                };
+                queue!(self.out, SetForegroundColor(color), Print(c))?;
+            }
+            queue!(
+                self.out,
+                SetForegroundColor(Color::Reset),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+
+        let counter = format!(
+            "  {}/{}{}",
+            finder.matches.len(),
+            finder.choices.len(),
+            if receiving { " ..." } else { "" }
+        );
+        for (row, notice) in finder.notices.iter().enumerate() {
+            queue!(
+                self.out,
+                cursor::MoveTo(0, (row + 2) as u16),
+                SetForegroundColor(Color::Yellow),
+                Print(
+                    format!("  {}", notice)
+                        .chars()
+                        .take(width)
+                        .collect::<String>()
+                ),
+                SetForegroundColor(Color::Reset)
+            )?;
+        }
+        let prompt = format!("> {}", finder.query);
+        queue!(
+            self.out,
+            cursor::MoveTo(0, 1),
+            SetForegroundColor(Color::DarkGrey),
+            Print(counter),
+            SetForegroundColor(Color::Reset),
+            cursor::MoveTo(0, 0),
+            Print(&prompt),
+            cursor::MoveTo(prompt.chars().count().min(width) as u16, 0)
+        )?;
+        self.out.flush()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = queue!(self.out, terminal::LeaveAlternateScreen).and_then(|()| self.out.flush());
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finder(choices: &[&str]) -> Finder {
+        let mut finder = Finder {
+            choices: choices.iter().map(|choice| choice.to_string()).collect(),
+            ..Finder::default()
+        };
+        finder.filter();
+        finder
+    }
+
+    fn press(finder: &mut Finder, code: KeyCode) {
+        finder.handle(KeyEvent::new(code, KeyModifiers::NONE), 10);
+    }
+
+    fn ctrl(finder: &mut Finder, c: char) -> Action {
+        finder.handle(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL), 10)
+    }
+
+    fn typed(finder: &mut Finder, text: &str) {
+        for c in text.chars() {
+            press(finder, KeyCode::Char(c));
+        }
+    }
+
+    fn matched(finder: &Finder) -> Vec<&str> {
+        finder
+            .matches
+            .iter()
+            .map(|(i, _)| finder.choices[*i].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn filter_puts_the_best_match_first() {
+        let mut finder = finder(&["/src/a-p-i", "/src/web", "/src/api"]);
+        assert_eq!(matched(&finder), ["/src/a-p-i", "/src/web", "/src/api"]);
+        typed(&mut finder, "api");
+        assert_eq!(matched(&finder), ["/src/api", "/src/a-p-i"]);
+        assert_eq!(finder.selected(), Some("/src/api"));
+    }
+
+    #[test]
+    fn filter_keeps_the_arrival_order_of_ties() {
+        let mut finder = finder(&["/work/web", "/oss/web", "/old/web"]);
+        typed(&mut finder, "web");
+        assert_eq!(matched(&finder), ["/work/web", "/oss/web", "/old/web"]);
+    }
+
+    #[test]
+    fn filter_keeps_the_cursor_on_a_match() {
+        let mut finder = finder(&["one", "two", "three"]);
+        press(&mut finder, KeyCode::Down);
+        press(&mut finder, KeyCode::Down);
+        assert_eq!(finder.selected(), Some("three"));
+
+        // choices arriving later do not move the selection
+        finder.choices.push("four".to_string());
+        finder.filter();
+        assert_eq!(finder.selected(), Some("three"));
+
+        // fewer matches pull the cursor back into the list
+        finder.query = "one".to_string();
+        finder.filter();
+        assert_eq!(finder.cursor, 0);
+        assert_eq!(finder.selected(), Some("one"));
+
+        finder.query = "nothing".to_string();
+        finder.filter();
+        assert_eq!(finder.selected(), None);
+    }
+
+    #[test]
+    fn move_cursor_stays_within_the_matches() {
+        let mut finder = finder(&["a", "b", "c"]);
+        press(&mut finder, KeyCode::Up);
+        assert_eq!(finder.cursor, 0);
+        press(&mut finder, KeyCode::PageDown);
+        assert_eq!(finder.cursor, 2);
+        ctrl(&mut finder, 'p');
+        assert_eq!(finder.selected(), Some("b"));
+        ctrl(&mut finder, 'n');
+        press(&mut finder, KeyCode::Tab);
+        assert_eq!(finder.selected(), Some("c"));
+    }
+
+    #[test]
+    fn editing_the_query_starts_over_at_the_best_match() {
+        let mut finder = finder(&["api", "web"]);
+        press(&mut finder, KeyCode::Down);
+        typed(&mut finder, "work api  ");
+        assert_eq!(finder.cursor, 0);
+
+        ctrl(&mut finder, 'w');
+        assert_eq!(finder.query, "work ");
+        press(&mut finder, KeyCode::Backspace);
+        assert_eq!(finder.query, "work");
+        ctrl(&mut finder, 'w');
+        assert_eq!(finder.query, "");
+        assert_eq!(matched(&finder), ["api", "web"]);
+
+        typed(&mut finder, "we");
+        ctrl(&mut finder, 'u');
+        assert_eq!(finder.query, "");
+    }
+
+    #[test]
+    fn keys_accept_and_abort() {
+        let mut finder = finder(&["api"]);
+        assert!(matches!(
+            finder.handle(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE), 10),
+            Action::Accept
+        ));
+        assert!(matches!(ctrl(&mut finder, 'c'), Action::Abort));
+        assert!(matches!(ctrl(&mut finder, 'x'), Action::Continue));
+    }
+}
@@ -0,0 +1,487 @@
+use crate::config::Config;
+use crate::discovery::Project;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::{Entry, HashMap};
+use std::env;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Maps characters that tmux rejects or interprets in target syntax, such
+/// as `.`, `:`, whitespace and non-ASCII characters, to a safe replacement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sanitizer {
+    pub replacement: char,
+    pub lowercase: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Sanitizer {
+            replacement: '_',
+            lowercase: false,
+        }
+    }
+}
+
+impl Sanitizer {
+    /// Uses the configured replacement unless it is unsafe itself, which
+    /// [`Config::validate`] rejects when loading a file, and falls back to
+    /// `_` then.
+    pub fn from_config(config: &Config) -> Self {
+        let default = Sanitizer::default();
+        Sanitizer {
+            replacement: config
+                .session_name_replacement
+                .filter(|&c| is_safe(c))
+                .unwrap_or(default.replacement),
+            lowercase: config.lowercase_session_names.unwrap_or(default.lowercase),
+        }
+    }
+
+    /// Returns `name` with every character that is unsafe in a tmux session
+    /// name replaced.
+    pub fn sanitize(&self, name: &str) -> String {
+        let sanitized: String = name
+            .chars()
+            .map(|c| {
+                if self.lowercase {
+                    c.to_ascii_lowercase()
+                } else {
+                    c
+                }
+            })
+            .map(|c| if is_safe(c) { c } else { self.replacement })
+            .collect();
+        if sanitized.is_empty() {
+            self.replacement.to_string()
+        } else {
+            sanitized
+        }
+    }
+}
+
+/// Returns whether `c` can be used in a session name as is.
+pub fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '/'
+}
+
+/// Selects a tmux server other than the default one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Socket {
+    /// A socket name, passed to tmux as `-L <name>`.
+    Name(String),
+    /// A socket path, passed to tmux as `-S <path>`.
+    Path(PathBuf),
+}
+
+impl Socket {
+    /// Returns the socket configured in `config`. `socket_path` takes
+    /// precedence over `socket_name`.
+    pub fn from_config(config: &Config) -> Option<Socket> {
+        Socket::new(config.socket_name.as_deref(), config.socket_path.as_deref())
+    }
+
+    /// Builds a socket from a name and a path, the path takes precedence.
+    pub fn new(name: Option<&str>, path: Option<&str>) -> Option<Socket> {
+        match (path, name) {
+            (Some(path), _) => Some(Socket::Path(PathBuf::from(
+                shellexpand::tilde(path).to_string(),
+            ))),
+            (None, Some(name)) => Some(Socket::Name(name.to_string())),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Creates and switches between tmux sessions.
+#[derive(Debug, Clone, Default)]
+pub struct SessionManager {
+    sanitizer: Sanitizer,
+    socket: Option<Socket>,
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        SessionManager::default()
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        SessionManager {
+            sanitizer: Sanitizer::from_config(config),
+            socket: Socket::from_config(config),
+        }
+    }
+
+    /// Uses the tmux server behind `socket` instead of the default one.
+    pub fn with_socket(mut self, socket: Option<Socket>) -> Self {
+        self.socket = socket;
+        self
+    }
+
+    /// Opens `project`, creating its session if it does not exist yet. Inside
+    /// a client of this manager's tmux server the client is switched to the
+    /// session, inside a client of another server the client is handed off,
+    /// see [`hand_off`](Self::hand_off), and from any other terminal the
+    /// session is attached.
+    pub fn open(&self, project: &Project) -> Result<(), Error> {
+        if let Some(socket) = &project.socket {
+            if self.socket.as_ref() != Some(socket) {
+                return self.for_project(project).open(project);
+            }
+        }
+
+        let session_name = self.resolve_session_name(project)?;
+
+        if self.inside_tmux() {
+            self.switch_client(&session_name, &project.path)
+        } else if client_socket().is_some() {
+            self.hand_off(&session_name, &project.path)
+        } else {
+            self.attach_session(&session_name, &project.path)
+        }
+    }
+
+    /// Returns the manager for the tmux server `project` lives on.
+    pub fn for_project(&self, project: &Project) -> SessionManager {
+        match &project.socket {
+            Some(socket) => self.clone().with_socket(Some(socket.clone())),
+            None => self.clone(),
+        }
+    }
+
+    /// Returns the session name to use for `project`. An existing session is
+    /// only reused if it was started in the project directory, otherwise a
+    /// numeric suffix is appended until a free or matching name is found.
+    pub fn resolve_session_name(&self, project: &Project) -> Result<String, Error> {
+        if !self.is_running() {
+            return Ok(self.sanitizer.sanitize(&project.session_name));
+        }
+        Ok(self.probe(project, &self.sessions()?).0)
+    }
+
+    /// Returns the session name [`open`](Self::open) would use for each of
+    /// `projects`, and whether that session is running. Each tmux server is
+    /// asked only once. Without tmux on PATH no session is running, so the
+    /// projects can still be listed.
+    pub fn resolve_session_names(
+        &self,
+        projects: &[Project],
+    ) -> Result<Vec<(String, bool)>, Error> {
+        let mut servers: HashMap<Option<Socket>, Vec<(String, PathBuf)>> = HashMap::new();
+        let mut resolved = Vec::new();
+        for project in projects {
+            let manager = self.for_project(project);
+            let sessions = match servers.entry(manager.socket.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(match manager.sessions() {
+                    Err(Error::MissingBinary(_)) => Vec::new(),
+                    sessions => sessions?,
+                }),
+            };
+            resolved.push(manager.probe(project, sessions));
+        }
+        Ok(resolved)
+    }
+
+    /// Returns the first session name for `project` that is free or taken by
+    /// a session started in the project directory, and whether it is taken.
+    fn probe(&self, project: &Project, sessions: &[(String, PathBuf)]) -> (String, bool) {
+        let base_name = self.sanitizer.sanitize(&project.session_name);
+        let mut session_name = base_name.clone();
+        let mut suffix = 1;
+        loop {
+            match sessions.iter().find(|(name, _)| *name == session_name) {
+                Some((_, path)) if !same_path(path, &project.path) => {
+                    suffix += 1;
+                    session_name = format!("{}{}{}", base_name, self.sanitizer.replacement, suffix);
+                }
+                Some(_) => return (session_name, true),
+                None => return (session_name, false),
+            }
+        }
+    }
+
+    /// Returns the start directory of the session called `session_name`, or
+    /// `None` if there is no such session.
+    pub fn session_path(&self, session_name: &str) -> Result<Option<PathBuf>, Error> {
+        let session_name = self.sanitizer.sanitize(session_name);
+        Ok(self
+            .sessions()?
+            .into_iter()
+            .find(|(name, _)| *name == session_name)
+            .map(|(_, path)| path))
+    }
+
+    /// Returns the name and start directory of every session.
+    pub fn sessions(&self) -> Result<Vec<(String, PathBuf)>, Error> {
+        let output = self
+            .tmux()
+            .arg("list-sessions")
+            .arg("-F")
+            .arg(SESSION_FORMAT)
+            .output()
+            .map_err(|err| Error::command("tmux", err))?;
+        // list-sessions fails when no server is running, which means there are no sessions
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(parse_session)
+            .collect())
+    }
+
+    /// Returns whether we are running inside a client of this manager's tmux
+    /// server, judged by the socket path recorded in `$TMUX`.
+    pub fn inside_tmux(&self) -> bool {
+        match client_socket() {
+            Some(socket) => same_path(&socket, &self.socket_path()),
+            None => false,
+        }
+    }
+
+    /// Returns the path of the socket of this manager's tmux server. Like
+    /// tmux itself this is the configured socket, the socket of the current
+    /// client if there is one, and `$TMUX_TMPDIR/tmux-<uid>/default` otherwise.
+    pub fn socket_path(&self) -> PathBuf {
+        let name = match &self.socket {
+            Some(Socket::Path(path)) => return path.clone(),
+            Some(Socket::Name(name)) => name.as_str(),
+            None => match client_socket() {
+                Some(socket) => return socket,
+                None => "default",
+            },
+        };
+
+        let tmpdir = env::var_os("TMUX_TMPDIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/tmp"));
+        // SAFETY: getuid cannot fail and has no preconditions
+        let uid = unsafe { libc::getuid() };
+        tmpdir.join(format!("tmux-{}", uid)).join(name)
+    }
+
+    /// Returns whether the tmux server behind [`socket_path`](Self::socket_path)
+    /// is running. A leftover socket of a crashed server does not count.
+    pub fn is_running(&self) -> bool {
+        let is_socket =
+            fs::metadata(self.socket_path()).is_ok_and(|metadata| metadata.file_type().is_socket());
+        is_socket
+            && self
+                .tmux()
+                .arg("list-sessions")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+    }
+
+    /// Returns whether a session called `session_name` exists.
+    pub fn has_session(&self, session_name: &str) -> Result<bool, Error> {
+        let session_name = self.sanitizer.sanitize(session_name);
+        Ok(self
+            .tmux()
+            .arg("has-session")
+            .arg("-t")
+            .arg(exact(&session_name))
+            .output()
+            .map_err(|err| Error::command("tmux", err))?
+            .status
+            .success())
+    }
+
+    /// Attaches to `session_name`, starting it in `path` first if needed.
+    /// Blocks until the client detaches.
+    pub fn attach_session(&self, session_name: &str, path: &Path) -> Result<(), Error> {
+        let session_name = self.sanitizer.sanitize(session_name);
+        run(self
+            .tmux()
+            .arg("new-session")
+            .arg("-A")
+            .arg("-s")
+            .arg(&session_name)
+            .arg("-c")
+            .arg(path))
+    }
+
+    /// Detaches the current client, which belongs to another tmux server,
+    /// and attaches its terminal to `session_name` on this manager's server,
+    /// creating the session in `path` first if needed. Attaching from inside
+    /// the client instead would nest one client in another, which tmux
+    /// refuses.
+    pub fn hand_off(&self, session_name: &str, path: &Path) -> Result<(), Error> {
+        let session_name = self.sanitizer.sanitize(session_name);
+        if !self.has_session(&session_name)? {
+            run(self
+                .tmux()
+                .arg("new-session")
+                .arg("-ds")
+                .arg(&session_name)
+                .arg("-c")
+                .arg(path))?;
+        }
+
+        let attach = format!(
+            "tmux -S {} attach-session -t {}",
+            shell_quote(&self.socket_path().to_string_lossy()),
+            shell_quote(&exact(&session_name))
+        );
+        // without -L or -S this goes to the server of the current client
+        run(Command::new("tmux")
+            .arg("detach-client")
+            .arg("-E")
+            .arg(attach))
+    }
+
+    /// Switches the current client to `session_name`, creating the session
+    /// in `path` first if needed.
+    pub fn switch_client(&self, session_name: &str, path: &Path) -> Result<(), Error> {
+        let session_name = self.sanitizer.sanitize(session_name);
+        if !self.has_session(&session_name)? {
+            run(self
+                .tmux()
+                .arg("new-session")
+                .arg("-ds")
+                .arg(&session_name)
+                .arg("-c")
+                .arg(path))?;
+        }
+
+        run(self
+            .tmux()
+            .arg("switch-client")
+            .arg("-t")
+            .arg(exact(&session_name)))
+    }
+
+    fn tmux(&self) -> Command {
+        let mut command = Command::new("tmux");
+        match &self.socket {
+            Some(Socket::Name(name)) => command.arg("-L").arg(name),
+            Some(Socket::Path(path)) => command.arg("-S").arg(path),
+            None => &mut command,
+        };
+        command
+    }
+}
+
+/// Shows `message` in the status line of the tmux client we are running in
+/// until a key is pressed, so it stays visible after switching sessions.
+/// Does nothing outside of tmux.
+pub fn display_message(message: &str) -> Result<(), Error> {
+    if client_socket().is_none() {
+        return Ok(());
+    }
+    // display-message expands formats, `##` is a literal `#`
+    run(Command::new("tmux")
+        .arg("display-message")
+        .arg("-d")
+        .arg("0")
+        .arg(message.replace('#', "##")))
+}
+
+/// Returns the server socket of the tmux client we are running in, taken
+/// from `$TMUX` which has the form `<socket>,<pid>,<session>`.
+fn client_socket() -> Option<PathBuf> {
+    let tmux = env::var_os("TMUX")?;
+    let tmux = tmux.to_string_lossy();
+    tmux.split(',')
+        .next()
+        .filter(|socket| !socket.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Format of the `list-sessions` lines read by [`parse_session`]. Session
+/// names cannot contain `:`, and tmux replaces control characters such as
+/// tabs in its output, so `:` separates the fields.
+const SESSION_FORMAT: &str = "#{session_name}:#{session_path}";
+
+/// Splits a `list-sessions` line printed with [`SESSION_FORMAT`] into the
+/// session name and start directory.
+fn parse_session(line: &str) -> Option<(String, PathBuf)> {
+    let (name, path) = line.split_once(':')?;
+    Some((name.to_string(), PathBuf::from(path)))
+}
+
+fn run(command: &mut Command) -> Result<(), Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .map_err(|err| Error::command(&program, err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::CommandFailed { program, status })
+    }
+}
+
+/// Prefixes `session_name` with `=` so tmux does not fall back to prefix or
+/// pattern matching when resolving the target.
+fn exact(session_name: &str) -> String {
+    format!("={}", session_name)
+}
+
+/// Quotes `s` for `sh`.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_session_splits_at_first_colon() {
+        assert_eq!(
+            parse_session("work/api:/home/me/work/api"),
+            Some(("work/api".to_string(), PathBuf::from("/home/me/work/api")))
+        );
+        assert_eq!(
+            parse_session("notes:/tmp/a:b"),
+            Some(("notes".to_string(), PathBuf::from("/tmp/a:b")))
+        );
+        assert_eq!(parse_session("no separator"), None);
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("/tmp/tmux-0/default"), "'/tmp/tmux-0/default'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn sanitize_replaces_unsafe_characters() {
+        let sanitizer = Sanitizer::default();
+        assert_eq!(sanitizer.sanitize("my.project"), "my_project");
+        assert_eq!(sanitizer.sanitize("host:8080"), "host_8080");
+        assert_eq!(sanitizer.sanitize("my project"), "my_project");
+        assert_eq!(sanitizer.sanitize("café"), "caf_");
+        assert_eq!(sanitizer.sanitize("work/api-v2_x"), "work/api-v2_x");
+    }
+
+    #[test]
+    fn sanitize_uses_replacement_and_lowercase() {
+        let sanitizer = Sanitizer {
+            replacement: '-',
+            lowercase: true,
+        };
+        assert_eq!(sanitizer.sanitize("My.Project"), "my-project");
+    }
+
+    #[test]
+    fn sanitize_never_returns_an_empty_name() {
+        assert_eq!(Sanitizer::default().sanitize(""), "_");
+    }
+}
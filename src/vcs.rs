@@ -0,0 +1,338 @@
+use crate::config::Config;
+use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The version control system a project is checked out with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Vcs {
+    Git(GitKind),
+    Mercurial,
+    Jujutsu,
+    Fossil,
+    Pijul,
+    Subversion,
+    /// Detected by a marker from the `vcs_markers` configuration.
+    Custom(String),
+}
+
+impl fmt::Display for Vcs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vcs::Git(_) => f.write_str("git"),
+            Vcs::Mercurial => f.write_str("mercurial"),
+            Vcs::Jujutsu => f.write_str("jujutsu"),
+            Vcs::Fossil => f.write_str("fossil"),
+            Vcs::Pijul => f.write_str("pijul"),
+            Vcs::Subversion => f.write_str("subversion"),
+            Vcs::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+/// The built-in detectors that can be enabled with the `vcs` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VcsKind {
+    Git,
+    Mercurial,
+    Jujutsu,
+    Fossil,
+    Pijul,
+    Subversion,
+}
+
+impl VcsKind {
+    pub const ALL: &'static [VcsKind] = &[
+        VcsKind::Git,
+        VcsKind::Mercurial,
+        VcsKind::Jujutsu,
+        VcsKind::Fossil,
+        VcsKind::Pijul,
+        VcsKind::Subversion,
+    ];
+
+    /// Name of the file or directory marking the root of a checkout.
+    pub fn marker(self) -> &'static str {
+        match self {
+            VcsKind::Git => ".git",
+            VcsKind::Mercurial => ".hg",
+            VcsKind::Jujutsu => ".jj",
+            VcsKind::Fossil => ".fslckout",
+            VcsKind::Pijul => ".pijul",
+            VcsKind::Subversion => ".svn",
+        }
+    }
+
+    fn detect(self, dir: &Path) -> Option<Vcs> {
+        match self {
+            VcsKind::Git => detect_git(dir).map(Vcs::Git),
+            _ if !dir.join(self.marker()).exists() => None,
+            VcsKind::Mercurial => Some(Vcs::Mercurial),
+            VcsKind::Jujutsu => Some(Vcs::Jujutsu),
+            VcsKind::Fossil => Some(Vcs::Fossil),
+            VcsKind::Pijul => Some(Vcs::Pijul),
+            VcsKind::Subversion => Some(Vcs::Subversion),
+        }
+    }
+}
+
+/// A user defined version control system, recognized by a file or directory
+/// called `marker` in the root of a checkout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VcsMarker {
+    pub name: String,
+    pub marker: String,
+}
+
+/// Recognizes checkouts of the enabled version control systems.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detector {
+    pub builtins: Vec<VcsKind>,
+    pub markers: Vec<VcsMarker>,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Detector {
+            builtins: VcsKind::ALL.to_vec(),
+            markers: Vec::new(),
+        }
+    }
+}
+
+impl Detector {
+    /// Builds the detector for the `vcs` and `vcs_markers` of `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Detector {
+            builtins: config.vcs.clone().unwrap_or_else(|| VcsKind::ALL.to_vec()),
+            markers: config.vcs_markers.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Returns the version control system `dir` is the root of a checkout
+    /// of. Jujutsu is checked before git, so colocated repositories are
+    /// reported as Jujutsu.
+    pub fn detect(&self, dir: &Path) -> Option<Vcs> {
+        let jujutsu = self
+            .builtins
+            .iter()
+            .filter(|&&kind| kind == VcsKind::Jujutsu);
+        let others = self
+            .builtins
+            .iter()
+            .filter(|&&kind| kind != VcsKind::Jujutsu);
+        jujutsu
+            .chain(others)
+            .find_map(|kind| kind.detect(dir))
+            .or_else(|| {
+                self.markers
+                    .iter()
+                    .find(|marker| dir.join(&marker.marker).exists())
+                    .map(|marker| Vcs::Custom(marker.name.clone()))
+            })
+    }
+
+    /// Returns whether `name` is the metadata directory of an enabled
+    /// version control system, which is never searched for projects.
+    pub fn is_metadata(&self, name: &str) -> bool {
+        self.builtins.iter().any(|kind| kind.marker() == name)
+            || self.markers.iter().any(|marker| marker.marker == name)
+    }
+}
+
+/// The different shapes a git checkout can take on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitKind {
+    /// A regular repository with a `.git` directory, or a `.git` file
+    /// pointing to its git directory, such as `gitdir: ./.bare`.
+    Repository,
+    /// A linked worktree, whose `.git` file points into another repository.
+    Worktree,
+    /// A bare repository without a working tree, such as `project.git`.
+    Bare,
+    /// A submodule, whose `.git` file points into the parent's `.git/modules`.
+    Submodule,
+}
+
+impl GitKind {
+    pub const ALL: &'static [GitKind] = &[
+        GitKind::Repository,
+        GitKind::Worktree,
+        GitKind::Bare,
+        GitKind::Submodule,
+    ];
+}
+
+/// Classifies `dir` as a git checkout, or returns `None` if it is none.
+pub fn detect_git(dir: &Path) -> Option<GitKind> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(GitKind::Repository);
+    }
+    if dot_git.is_file() {
+        // worktrees, submodules and repositories with a separate git directory
+        // all use a gitlink file, but only the git directory of a worktree has a
+        // `commondir` pointing to the main repository, and only that of a
+        // submodule lives in the `modules` directory of its parent
+        return match read_gitdir(&dot_git) {
+            Some(gitdir) if gitdir.join("commondir").is_file() => Some(GitKind::Worktree),
+            Some(gitdir) if is_module(&gitdir) => Some(GitKind::Submodule),
+            _ => Some(GitKind::Repository),
+        };
+    }
+    if is_bare(dir) {
+        return Some(GitKind::Bare);
+    }
+    None
+}
+
+/// Returns the working directories of the worktrees linked to the bare
+/// repository at `dir`, as recorded in `worktrees/*/gitdir`.
+pub fn bare_worktrees(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir.join("worktrees")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| fs::read_to_string(entry.path().join("gitdir")).ok())
+        .filter_map(|gitdir| Path::new(gitdir.trim()).parent().map(Path::to_path_buf))
+        .filter(|worktree| worktree.is_dir())
+        .collect()
+}
+
+/// Returns whether `gitdir` lies in the `modules` directory of a parent
+/// repository's git directory, possibly of a nested submodule.
+fn is_module(gitdir: &Path) -> bool {
+    let gitdir = gitdir.clean();
+    gitdir.ancestors().skip(1).any(|dir| {
+        dir.file_name().is_some_and(|name| name == "modules")
+            && dir
+                .parent()
+                .is_some_and(|parent| parent.join("HEAD").is_file())
+    })
+}
+
+fn is_bare(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+/// Reads the `gitdir: <path>` line of a gitlink file, resolving relative
+/// paths against the directory containing it.
+fn read_gitdir(dot_git: &Path) -> Option<PathBuf> {
+    let content = fs::read_to_string(dot_git).ok()?;
+    let gitdir = content
+        .lines()
+        .find_map(|line| line.strip_prefix("gitdir:"))?;
+    let gitdir = Path::new(gitdir.trim());
+    Some(match dot_git.parent() {
+        Some(parent) if gitdir.is_relative() => parent.join(gitdir),
+        _ => gitdir.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A temporary directory that is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path =
+                env::temp_dir().join(format!("tmux-sessionizer-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn write(&self, path: &str, content: &str) -> &TempDir {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+            self
+        }
+
+        fn mkdir(&self, path: &str) -> &TempDir {
+            fs::create_dir_all(self.0.join(path)).unwrap();
+            self
+        }
+
+        /// Lays out a bare repository, or the git directory of a checkout.
+        fn git_dir(&self, path: &str) -> &TempDir {
+            self.write(&format!("{}/HEAD", path), "ref: refs/heads/main\n")
+                .mkdir(&format!("{}/objects", path))
+                .mkdir(&format!("{}/refs", path))
+        }
+
+        fn detect(&self, path: &str) -> Option<GitKind> {
+            detect_git(&self.0.join(path))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn detect_git_finds_a_repository() {
+        let dir = TempDir::new("repository");
+        dir.git_dir("repo/.git").mkdir("plain");
+        assert_eq!(dir.detect("repo"), Some(GitKind::Repository));
+        assert_eq!(dir.detect("plain"), None);
+    }
+
+    #[test]
+    fn detect_git_finds_a_worktree() {
+        let dir = TempDir::new("worktree");
+        dir.git_dir("main/.git")
+            .write("main/.git/worktrees/wt/commondir", "../..\n")
+            .write("wt/.git", "gitdir: ../main/.git/worktrees/wt\n");
+        assert_eq!(dir.detect("wt"), Some(GitKind::Worktree));
+    }
+
+    #[test]
+    fn detect_git_finds_a_submodule() {
+        let dir = TempDir::new("submodule");
+        dir.git_dir("parent/.git")
+            .git_dir("parent/.git/modules/sub")
+            .write("parent/sub/.git", "gitdir: ../.git/modules/sub\n");
+        assert_eq!(dir.detect("parent/sub"), Some(GitKind::Submodule));
+    }
+
+    #[test]
+    fn detect_git_finds_a_repository_with_a_separate_git_directory() {
+        let dir = TempDir::new("separate-gitdir");
+        dir.git_dir("repo/.bare")
+            .write("repo/.git", "gitdir: ./.bare\n");
+        assert_eq!(dir.detect("repo"), Some(GitKind::Repository));
+    }
+
+    #[test]
+    fn detect_git_finds_a_bare_repository() {
+        let dir = TempDir::new("bare");
+        dir.git_dir("repo.git");
+        assert_eq!(dir.detect("repo.git"), Some(GitKind::Bare));
+    }
+
+    #[test]
+    fn bare_worktrees_skips_removed_worktrees() {
+        let dir = TempDir::new("bare-worktrees");
+        let gitdir = |name: &str| format!("{}\n", dir.0.join(name).join(".git").display());
+        dir.git_dir("repo.git")
+            .mkdir("wt")
+            .write("repo.git/worktrees/wt/gitdir", &gitdir("wt"))
+            .write("repo.git/worktrees/gone/gitdir", &gitdir("gone"));
+        assert_eq!(bare_worktrees(&dir.0.join("repo.git")), [dir.0.join("wt")]);
+    }
+}